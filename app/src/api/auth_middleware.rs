@@ -1,19 +1,73 @@
-use actix_web::{Error, dev::{self, Service, ServiceRequest, ServiceResponse, Transform}, HttpResponse};
+use actix_web::{Error, dev::{self, Service, ServiceRequest, ServiceResponse, Transform}, web, HttpMessage, HttpResponse};
+use actix_web::http::{header, Method};
 use std::future::{ready, Ready};
+use std::rc::Rc;
+use chrono::NaiveDateTime;
+use log::{debug, warn};
 use actix_web::body::EitherBody;
 use futures_util::future::LocalBoxFuture;
-use crate::model::api_key::ApiKeyDb;
-use crate::web::DbPool;
+use crate::api::{KeyCache, LastUsedTracker, Metrics};
+use crate::api::jwt::{looks_like_jwt, JwtVerifier};
+use crate::config::{AuthHeaderPrecedence, AuthMode, Config};
+use crate::model::api_key::Scope;
+use crate::storage::Storage;
 
 static API_KEY_HEADER : &str = "x-api-key";
+static BEARER_PREFIX : &str = "Bearer ";
+static AUTH_REALM : &str = "url";
+
+/// The authenticated caller, made available to handlers through the request extensions
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub key_id : i64,
+    pub scopes : Vec<Scope>
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope : Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// The owner links have to belong to for this caller to manage them, or `None` if it may manage any link
+    pub fn link_owner_filter(&self) -> Option<i64> {
+        if self.has_scope(Scope::LinksAdmin) {
+            None
+        } else {
+            Some(self.key_id)
+        }
+    }
+}
 
 pub struct AuthMiddleware {
-    pub pool: DbPool
+    pub storage: web::Data<dyn Storage>,
+    /// Scopes the caller must hold, either for every request or only for a given request method
+    pub required: Vec<(Option<Method>, Scope)>
+}
+
+impl AuthMiddleware {
+    pub fn new(storage : web::Data<dyn Storage>) -> Self {
+        Self {
+            storage,
+            required: Vec::new()
+        }
+    }
+
+    /// Requires the given scope for every request
+    pub fn require(mut self, scope : Scope) -> Self {
+        self.required.push((None, scope));
+        self
+    }
+
+    /// Requires the given scope for requests using the given method
+    pub fn require_for(mut self, method : Method, scope : Scope) -> Self {
+        self.required.push((Some(method), scope));
+        self
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
     where
-        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
         S::Future: 'static,
         B: 'static,
 {
@@ -24,18 +78,19 @@ impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(DefaultAuthMiddleware { service, pool: self.pool.clone() }))
+        ready(Ok(DefaultAuthMiddleware { service: Rc::new(service), storage: self.storage.clone(), required: self.required.clone() }))
     }
 }
 
 pub struct DefaultAuthMiddleware<S> {
-    service: S,
-    pool: DbPool
+    service: Rc<S>,
+    storage: web::Data<dyn Storage>,
+    required: Vec<(Option<Method>, Scope)>
 }
 
 impl<S, B> Service<ServiceRequest> for DefaultAuthMiddleware<S>
     where
-        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
         S::Future: 'static,
         B: 'static,
 {
@@ -46,36 +101,175 @@ impl<S, B> Service<ServiceRequest> for DefaultAuthMiddleware<S>
     dev::forward_ready!(service);
 
     fn call(&self, request: ServiceRequest) -> Self::Future {
-        if request.headers().contains_key(API_KEY_HEADER) {
-            use crate::model::api_key::db::api_keys::*;
-            use crate::model::api_key::db::api_keys::dsl::api_keys;
-            use diesel::{RunQueryDsl, QueryDsl, ExpressionMethods};
-
-            let conn = self.pool.get().map_err(actix_web::error::ErrorInternalServerError).unwrap();
-            let keys : Vec<ApiKeyDb> = api_keys
-                .filter(key.eq(request.headers().get(API_KEY_HEADER).unwrap().to_str().unwrap()))
-                .limit(1)
-                .load::<ApiKeyDb>(&conn)
-                .expect("Unable to find API key entry");
-
-            if keys.len() == 0 {
-                let resp = HttpResponse::Unauthorized().finish().map_into_right_body();
-                let (request, _pl) = request.into_parts();
-                return Box::pin(async {
-                    Ok(ServiceResponse::new(request, resp))
-                });
+        let (precedence, auth_mode) = request.app_data::<web::Data<Config>>()
+            .map(|conf| (conf.auth_header_precedence, conf.auth_mode))
+            .unwrap_or_default();
+        let presented_key = match presented_key(&request, precedence) {
+            Some(val) => val,
+            None => return Box::pin(async {
+                record_failure(&request, "missing");
+                Ok(respond(request, unauthorized(None)))
+            })
+        };
+
+        let service = self.service.clone();
+        let storage = self.storage.clone();
+        let required = self.required.clone();
+        Box::pin(async move {
+            let now = chrono::Utc::now().naive_utc();
+            let cache = request.app_data::<web::Data<KeyCache>>().cloned();
+            let verifier = request.app_data::<web::Data<JwtVerifier>>().cloned();
+
+            let principal = match cache.as_ref().and_then(|cache| cache.get(&presented_key, now)) {
+                Some(val) => Some(val),
+                None => {
+                    let lookup = match authenticate(storage, presented_key.clone(), auth_mode, verifier, now).await {
+                        Ok(val) => val,
+                        Err(err) => {
+                            warn!("Unable to look up API key: {}", err);
+                            return Ok(respond(request, HttpResponse::InternalServerError().finish()));
+                        }
+                    };
+                    if let (Some(cache), Some((principal, valid_until))) = (&cache, &lookup) {
+                        cache.insert(&presented_key, principal.clone(), *valid_until);
+                    }
+                    lookup.map(|(principal, _)| principal)
+                }
+            };
+
+            let principal = match principal {
+                Some(val) => val,
+                None => {
+                    record_failure(&request, "invalid");
+                    return Ok(respond(request, unauthorized(Some("invalid_token"))));
+                }
+            };
+
+            if let Some(tracker) = request.app_data::<web::Data<LastUsedTracker>>() {
+                tracker.touch(principal.key_id);
             }
-        } else {
-            let resp = HttpResponse::Unauthorized().finish().map_into_right_body();
-            let (request, _pl) = request.into_parts();
-            return Box::pin(async {
-                Ok(ServiceResponse::new(request, resp))
-            });
-        }
 
-        let res = self.service.call(request);
-        return Box::pin(async {
-            res.await.map(ServiceResponse::map_into_left_body)
+            let missing_scopes : Vec<Scope> = required.iter()
+                .filter(|(method, _)| method.as_ref().map_or(true, |method| method == request.method()))
+                .map(|(_, scope)| *scope)
+                .filter(|scope| !principal.has_scope(*scope))
+                .collect();
+            if !missing_scopes.is_empty() {
+                let challenge = format!("Bearer realm=\"{}\", error=\"insufficient_scope\", scope=\"{}\"", AUTH_REALM,
+                                        missing_scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(" "));
+                let resp = HttpResponse::Forbidden()
+                    .insert_header((header::WWW_AUTHENTICATE, challenge))
+                    .finish();
+                record_failure(&request, "insufficient_scope");
+                return Ok(respond(request, resp));
+            }
+
+            request.extensions_mut().insert(principal);
+            service.call(request).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+/// The key presented by the caller, through either `x-api-key` or `Authorization: Bearer`. When both are present,
+/// `precedence` decides which is used.
+fn presented_key(request : &ServiceRequest, precedence : AuthHeaderPrecedence) -> Option<String> {
+    let header_value = |name| {
+        request.headers().get(name)
+            .and_then(|val : &header::HeaderValue| val.to_str().ok())
+    };
+    let api_key = header_value(API_KEY_HEADER);
+    let bearer = header_value(header::AUTHORIZATION.as_str())
+        .and_then(|val| {
+            // The scheme is case-insensitive
+            if val.len() > BEARER_PREFIX.len() && val[..BEARER_PREFIX.len()].eq_ignore_ascii_case(BEARER_PREFIX) {
+                Some(val[BEARER_PREFIX.len()..].trim())
+            } else {
+                None
+            }
         });
+
+    let presented = match precedence {
+        AuthHeaderPrecedence::ApiKey => api_key.or(bearer),
+        AuthHeaderPrecedence::Bearer => bearer.or(api_key)
+    };
+    presented.map(|val| val.to_owned())
+}
+
+/// Counts a rejected request in the metrics, if they're set up
+fn record_failure(request : &ServiceRequest, reason : &str) {
+    if let Some(metrics) = request.app_data::<web::Data<Metrics>>() {
+        metrics.record_auth_failure(reason);
     }
+}
+
+/// 401 response with a challenge telling the client how to authenticate, see RFC 6750
+fn unauthorized(error : Option<&str>) -> HttpResponse {
+    let challenge = match error {
+        Some(error) => format!("Bearer realm=\"{}\", error=\"{}\"", AUTH_REALM, error),
+        None => format!("Bearer realm=\"{}\"", AUTH_REALM)
+    };
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, challenge))
+        .finish()
+}
+
+/// Ends the request with the given response, without calling the wrapped service
+fn respond<B>(request : ServiceRequest, resp : HttpResponse) -> ServiceResponse<EitherBody<B>> {
+    let (request, _pl) = request.into_parts();
+    ServiceResponse::new(request, resp.map_into_right_body())
+}
+
+/// Finds the caller presenting `presented_key`, which is either a JWT or an API key depending on `auth_mode`.
+/// Returns the caller and until when the presented credential is valid.
+async fn authenticate(storage : web::Data<dyn Storage>, presented_key : String, auth_mode : AuthMode, verifier : Option<web::Data<JwtVerifier>>,
+                      now : NaiveDateTime) -> Result<Option<(Principal, Option<NaiveDateTime>)>, String> {
+    if auth_mode.accepts_jwts() && looks_like_jwt(&presented_key) {
+        match verifier.as_ref().map(|verifier| verifier.verify(&presented_key)) {
+            Some(Ok(identity)) => {
+                // The subject owns the links it creates through a key record of its own
+                let subject = identity.subject.clone();
+                let moved_storage = storage.clone();
+                let key_id = web::block(move || moved_storage.subject_key_id(&subject)).await
+                    .map_err(|err| err.to_string())?
+                    .map_err(|err| err.err_msg())?;
+                return Ok(Some((Principal { key_id, scopes: identity.scopes }, identity.expires_at)));
+            },
+            Some(Err(err)) => debug!("Rejected JWT: {}", err),
+            None => warn!("JWTs are accepted, but no JWKS is loaded")
+        }
+    }
+
+    if !auth_mode.accepts_api_keys() {
+        return Ok(None);
+    }
+    web::block(move || find_key(storage.get_ref(), &presented_key, now)).await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.err_msg())
+}
+
+/// Looks up the key matching `presented_key`, returning the caller it belongs to and until when the presented secret
+/// is valid. Blocking, so has to be run off the async workers.
+fn find_key(storage : &dyn Storage, presented_key : &str, now : NaiveDateTime) -> Result<Option<(Principal, Option<NaiveDateTime>)>, crate::model::error::Error> {
+    // Only the prefix of a key is stored in plaintext, so narrow down on that before verifying the hash.
+    // Keys that were recently rotated may still be presented with their previous secret.
+    let presented_prefix = crate::model::api_key::key_prefix(presented_key);
+    let candidates = storage.find_keys_by_prefix(&presented_prefix)?;
+
+    let matched = candidates.into_iter()
+        .filter(|candidate| !candidate.is_expired(now))
+        .find_map(|candidate| {
+            let valid_until = if candidate.verify(presented_key) {
+                candidate.expires_at
+            } else if candidate.verify_previous(presented_key, now) {
+                // Grace period of a rotated out secret, which can't outlast the key itself
+                match (candidate.previous_key_expires_at, candidate.expires_at) {
+                    (Some(grace_until), Some(key_expires_at)) => Some(grace_until.min(key_expires_at)),
+                    (grace_until, key_expires_at) => grace_until.or(key_expires_at)
+                }
+            } else {
+                return None;
+            };
+            Some((Principal { key_id: candidate.id, scopes: candidate.scopes() }, valid_until))
+        });
+    Ok(matched)
 }
\ No newline at end of file
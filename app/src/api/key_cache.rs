@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use chrono::NaiveDateTime;
+use crate::api::Principal;
+
+/// Amount of cached keys kept before stale ones are cleaned up
+const PRUNE_THRESHOLD : usize = 1_000;
+
+struct CachedKey {
+    principal : Principal,
+    /// When the key, or the rotated out secret it was presented with, stops being valid
+    valid_until : Option<NaiveDateTime>,
+    cached_at : Instant
+}
+
+/// Keys validated by `AuthMiddleware` during the last `ttl`, so not every request has to hit the database.
+/// Entries are looked up by an unsalted hash of the presented key, so plaintext keys aren't kept in memory.
+///
+/// Keys are invalidated when they are rotated or deleted through this instance. Other instances sharing the database
+/// keep accepting such keys until their entry expires.
+pub struct KeyCache {
+    ttl : Duration,
+    entries : Mutex<HashMap<String, CachedKey>>
+}
+
+impl KeyCache {
+    pub fn new(ttl_secs : u64) -> Self {
+        Self {
+            ttl: Duration::from_secs(ttl_secs),
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub fn get(&self, presented_key : &str, now : NaiveDateTime) -> Option<Principal> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let cache_key = Self::cache_key(presented_key);
+        let fresh = entries.get(&cache_key)
+            .map(|entry| entry.cached_at.elapsed() < self.ttl && entry.valid_until.map_or(true, |valid_until| valid_until > now));
+        match fresh {
+            Some(true) => entries.get(&cache_key).map(|entry| entry.principal.clone()),
+            Some(false) => {
+                entries.remove(&cache_key);
+                None
+            },
+            None => None
+        }
+    }
+
+    pub fn insert(&self, presented_key : &str, principal : Principal, valid_until : Option<NaiveDateTime>) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= PRUNE_THRESHOLD {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.cached_at.elapsed() < ttl);
+        }
+        entries.insert(Self::cache_key(presented_key), CachedKey { principal, valid_until, cached_at: Instant::now() });
+    }
+
+    /// Drops every cached secret of the given key, for when it is deleted or rotated
+    pub fn invalidate(&self, key_id : i64) {
+        self.entries.lock().unwrap().retain(|_, entry| entry.principal.key_id != key_id);
+    }
+
+    fn cache_key(presented_key : &str) -> String {
+        crate::model::api_key::hash_key(presented_key, "")
+    }
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::{NaiveDateTime, Utc};
+
+/// Collects when API keys were last used, so `AuthMiddleware` doesn't have to write to the database on every
+/// request. The collected timestamps are periodically written by `tasks::flush_key_last_used`.
+#[derive(Default)]
+pub struct LastUsedTracker {
+    pending : Mutex<HashMap<i64, NaiveDateTime>>
+}
+
+impl LastUsedTracker {
+    pub fn touch(&self, key_id : i64) {
+        self.pending.lock().unwrap().insert(key_id, Utc::now().naive_utc());
+    }
+
+    /// Takes all timestamps collected since the last call
+    pub fn drain(&self) -> HashMap<i64, NaiveDateTime> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
\ No newline at end of file
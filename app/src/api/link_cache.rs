@@ -0,0 +1,223 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lru::LruCache;
+use prometheus::{IntCounter, Registry};
+use serde::{Serialize, Deserialize};
+use crate::model::url::UrlDb;
+
+struct CachedLink {
+    /// `None` when the link doesn't exist
+    link : Option<UrlDb>,
+    cached_at : Instant
+}
+
+/// Counters of `LinkCache`, as returned by `GET /cache/stats`
+#[derive(Serialize, Deserialize)]
+pub struct LinkCacheStats {
+    pub entries : usize,
+    pub capacity : usize,
+    pub hits : u64,
+    pub misses : u64
+}
+
+/// Links looked up by their short id during the last `ttl`, so redirecting to popular links doesn't hit the database
+/// every time. Ids that don't exist are remembered for `negative_ttl`. Once `capacity` links are cached, the least
+/// recently used one makes way.
+///
+/// Entries are dropped when a link is created, updated or deleted through this instance. Other instances sharing the
+/// database only see such changes once the entry expires.
+pub struct LinkCache {
+    capacity : usize,
+    ttl : Duration,
+    negative_ttl : Duration,
+    entries : Mutex<LruCache<String, CachedLink>>,
+    hits : IntCounter,
+    misses : IntCounter
+}
+
+impl LinkCache {
+    pub fn new(capacity : usize, ttl_secs : u64, negative_ttl_secs : u64) -> Self {
+        Self {
+            capacity,
+            ttl: Duration::from_secs(ttl_secs),
+            negative_ttl: Duration::from_secs(negative_ttl_secs),
+            entries: Mutex::new(LruCache::new(capacity.max(1))),
+            hits: IntCounter::new("url_link_cache_hits_total", "Link lookups answered by the redirect cache").unwrap(),
+            misses: IntCounter::new("url_link_cache_misses_total", "Link lookups that had to go to the database").unwrap()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && !self.ttl.is_zero()
+    }
+
+    /// Returns `None` when the link has to be looked up, and `Some(None)` when it's known not to exist
+    pub fn get(&self, link_id : &str) -> Option<Option<UrlDb>> {
+        self.get_at(link_id, Instant::now())
+    }
+
+    fn get_at(&self, link_id : &str, now : Instant) -> Option<Option<UrlDb>> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = entries.get(link_id).map(|entry| {
+            let ttl = if entry.link.is_some() { self.ttl } else { self.negative_ttl };
+            now.duration_since(entry.cached_at) < ttl
+        });
+        let cached = match fresh {
+            Some(true) => entries.get(link_id).map(|entry| entry.link.clone()),
+            Some(false) => {
+                entries.pop(link_id);
+                None
+            },
+            None => None
+        };
+
+        match cached {
+            Some(_) => self.hits.inc(),
+            None => self.misses.inc()
+        }
+        cached
+    }
+
+    pub fn insert(&self, link_id : &str, link : Option<UrlDb>) {
+        self.insert_at(link_id, link, Instant::now())
+    }
+
+    fn insert_at(&self, link_id : &str, link : Option<UrlDb>, now : Instant) {
+        if !self.is_enabled() || (link.is_none() && self.negative_ttl.is_zero()) {
+            return;
+        }
+
+        self.entries.lock().unwrap().put(link_id.to_owned(), CachedLink { link, cached_at: now });
+    }
+
+    /// Drops the cached link, for when it is created, updated or deleted
+    pub fn invalidate(&self, link_id : &str) {
+        self.entries.lock().unwrap().pop(link_id);
+    }
+
+    pub fn stats(&self) -> LinkCacheStats {
+        LinkCacheStats {
+            entries: self.entries.lock().unwrap().len(),
+            capacity: self.capacity,
+            hits: self.hits.get(),
+            misses: self.misses.get()
+        }
+    }
+
+    /// Adds the hit and miss counters to the metrics in `registry`
+    pub fn register(&self, registry : &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.hits.clone()))?;
+        registry.register(Box::new(self.misses.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(id : &str) -> UrlDb {
+        UrlDb {
+            id: id.to_owned(),
+            url: format!("https://example.com/{}", id),
+            expires_at: None,
+            redirect_type: None,
+            updated_at: None,
+            created_at: chrono::Utc::now().naive_utc(),
+            created_by: None,
+            title: None
+        }
+    }
+
+    fn cached_id(cached : Option<Option<UrlDb>>) -> Option<Option<String>> {
+        cached.map(|link| link.map(|link| link.id))
+    }
+
+    #[test]
+    fn serves_links_until_ttl() {
+        let cache = LinkCache::new(10, 60, 10);
+        let now = Instant::now();
+
+        assert!(cache.get_at("a", now).is_none());
+        cache.insert_at("a", Some(link("a")), now);
+        assert_eq!(cached_id(cache.get_at("a", now + Duration::from_secs(59))), Some(Some("a".to_owned())));
+        assert!(cache.get_at("a", now + Duration::from_secs(60)).is_none());
+        // The expired entry was dropped
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn remembers_unknown_ids_for_negative_ttl() {
+        let cache = LinkCache::new(10, 60, 10);
+        let now = Instant::now();
+
+        cache.insert_at("missing", None, now);
+        assert_eq!(cached_id(cache.get_at("missing", now + Duration::from_secs(9))), Some(None));
+        assert!(cache.get_at("missing", now + Duration::from_secs(10)).is_none());
+    }
+
+    #[test]
+    fn zero_negative_ttl_skips_unknown_ids() {
+        let cache = LinkCache::new(10, 60, 0);
+        let now = Instant::now();
+
+        cache.insert_at("missing", None, now);
+        assert!(cache.get_at("missing", now).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn invalidate_drops_entry() {
+        let cache = LinkCache::new(10, 60, 10);
+        let now = Instant::now();
+
+        cache.insert_at("a", Some(link("a")), now);
+        cache.insert_at("missing", None, now);
+        cache.invalidate("a");
+        cache.invalidate("missing");
+        assert!(cache.get_at("a", now).is_none());
+        assert!(cache.get_at("missing", now).is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = LinkCache::new(2, 60, 10);
+        let now = Instant::now();
+
+        cache.insert_at("a", Some(link("a")), now);
+        cache.insert_at("b", Some(link("b")), now);
+        // Using a makes b the least recently used
+        assert!(cache.get_at("a", now).is_some());
+        cache.insert_at("c", Some(link("c")), now);
+
+        assert!(cache.get_at("a", now).is_some());
+        assert!(cache.get_at("b", now).is_none());
+        assert!(cache.get_at("c", now).is_some());
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        for cache in [LinkCache::new(0, 60, 10), LinkCache::new(10, 0, 10)] {
+            let now = Instant::now();
+            cache.insert_at("a", Some(link("a")), now);
+            assert!(cache.get_at("a", now).is_none());
+        }
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = LinkCache::new(10, 60, 10);
+        let now = Instant::now();
+
+        cache.get_at("a", now);
+        cache.insert_at("a", Some(link("a")), now);
+        cache.get_at("a", now);
+        cache.get_at("a", now);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,155 @@
+use actix_web::{Error, dev::{self, Service, ServiceRequest, ServiceResponse, Transform}, web};
+use actix_web::http::StatusCode;
+use std::future::{ready, Ready};
+use std::time::Instant;
+use futures_util::future::LocalBoxFuture;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use crate::api::LinkCache;
+use crate::storage::PoolStatus;
+
+/// Route label of requests that didn't match any route
+static UNMATCHED_ROUTE : &str = "unmatched";
+
+/// Prometheus metrics of the service, rendered by `GET /metrics`
+pub struct Metrics {
+    registry : Registry,
+    http_requests : IntCounterVec,
+    http_request_duration : HistogramVec,
+    redirects : IntCounterVec,
+    auth_failures : IntCounterVec,
+    db_pool_connections : IntGaugeVec,
+    db_pool_max_connections : IntGauge,
+    links : IntGauge,
+    api_keys : IntGauge
+}
+
+impl Metrics {
+    pub fn new(link_cache : &LinkCache) -> prometheus::Result<Self> {
+        let metrics = Self {
+            registry: Registry::new(),
+            http_requests: IntCounterVec::new(
+                Opts::new("url_http_requests_total", "HTTP requests handled, by route, method and status code"),
+                &["route", "method", "status"])?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("url_http_request_duration_seconds", "Time taken to handle HTTP requests, by route and method"),
+                &["route", "method"])?,
+            redirects: IntCounterVec::new(
+                Opts::new("url_redirects_total", "Requests for short links, by response status code"),
+                &["status"])?,
+            auth_failures: IntCounterVec::new(
+                Opts::new("url_auth_failures_total", "Requests rejected by authentication, by reason"),
+                &["reason"])?,
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new("url_db_pool_connections", "Database connections in the pool, by whether they are in use"),
+                &["state"])?,
+            db_pool_max_connections: IntGauge::new("url_db_pool_max_connections", "Maximum amount of database connections in the pool")?,
+            links: IntGauge::new("url_links", "Links stored")?,
+            api_keys: IntGauge::new("url_api_keys", "API keys stored, including the records of JWT subjects")?
+        };
+
+        metrics.registry.register(Box::new(metrics.http_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_request_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.redirects.clone()))?;
+        metrics.registry.register(Box::new(metrics.auth_failures.clone()))?;
+        metrics.registry.register(Box::new(metrics.db_pool_connections.clone()))?;
+        metrics.registry.register(Box::new(metrics.db_pool_max_connections.clone()))?;
+        metrics.registry.register(Box::new(metrics.links.clone()))?;
+        metrics.registry.register(Box::new(metrics.api_keys.clone()))?;
+        link_cache.register(&metrics.registry)?;
+        Ok(metrics)
+    }
+
+    pub fn record_redirect(&self, status : StatusCode) {
+        self.redirects.with_label_values(&[status.as_str()]).inc();
+    }
+
+    /// `reason` is one of `missing`, `invalid` or `insufficient_scope`
+    pub fn record_auth_failure(&self, reason : &str) {
+        self.auth_failures.with_label_values(&[reason]).inc();
+    }
+
+    /// Renders every metric in the Prometheus text format. `totals` are the amount of links and keys, which are left
+    /// at their previous values when they couldn't be counted.
+    pub fn render(&self, pool : PoolStatus, totals : Option<(i64, i64)>) -> prometheus::Result<String> {
+        let idle = pool.idle_connections as i64;
+        self.db_pool_connections.with_label_values(&["idle"]).set(idle);
+        self.db_pool_connections.with_label_values(&["active"]).set(pool.connections as i64 - idle);
+        self.db_pool_max_connections.set(pool.max_size as i64);
+        if let Some((links, api_keys)) = totals {
+            self.links.set(links);
+            self.api_keys.set(api_keys);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Records the count and latency of every request in `Metrics`
+pub struct RequestMetrics {
+    metrics : web::Data<Metrics>
+}
+
+impl RequestMetrics {
+    pub fn new(metrics : web::Data<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service, metrics: self.metrics.clone() }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+    metrics: web::Data<Metrics>
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Label by route pattern rather than path, so every short link doesn't get a series of its own
+        let route = req.match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+        let method = req.method().as_str().to_owned();
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code()
+            };
+            metrics.http_requests.with_label_values(&[&route, &method, status.as_str()]).inc();
+            metrics.http_request_duration.with_label_values(&[&route, &method]).observe(started.elapsed().as_secs_f64());
+
+            res
+        })
+    }
+}
\ No newline at end of file
@@ -1,5 +1,17 @@
 pub mod auth_middleware;
 pub mod default_headers_middleware;
+pub mod jwt;
+pub mod key_cache;
+pub mod last_used;
+pub mod link_cache;
+pub mod metrics_middleware;
+pub mod rate_limit_middleware;
 
 pub use default_headers_middleware::DefaultHeaders;
-pub use auth_middleware::AuthMiddleware;
\ No newline at end of file
+pub use auth_middleware::{AuthMiddleware, Principal};
+pub use jwt::JwtVerifier;
+pub use key_cache::KeyCache;
+pub use last_used::LastUsedTracker;
+pub use link_cache::LinkCache;
+pub use metrics_middleware::{Metrics, RequestMetrics};
+pub use rate_limit_middleware::{client_ip, ForwardedIp, RateLimit, RateLimiter};
\ No newline at end of file
@@ -0,0 +1,365 @@
+use actix_web::{Error, dev::{self, Service, ServiceRequest, ServiceResponse, Transform}, HttpMessage, HttpResponse};
+use actix_web::http::header::HeaderMap;
+use std::future::{ready, Ready};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use actix_web::body::EitherBody;
+use futures_util::future::LocalBoxFuture;
+use lru::LruCache;
+use crate::api::Principal;
+use crate::config::Config;
+
+/// Amount of buckets kept. Once reached, the least recently used bucket makes way, so clients cycling through
+/// addresses can't grow the map without bound.
+const MAX_BUCKETS : usize = 10_000;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum BucketKey {
+    ApiKey(i64),
+    Ip(String),
+    /// Shared by every caller without a known client IP, see `RateLimitMiddleware::bucket_key`
+    Unidentified
+}
+
+struct Bucket {
+    tokens : f64,
+    updated : Instant
+}
+
+/// Token buckets shared by every worker. Each caller may burst up to `burst` requests, after which tokens refill at
+/// `per_minute` a minute.
+pub struct RateLimiter {
+    burst : f64,
+    per_second : f64,
+    /// See `client_ip`
+    forwarded : Option<ForwardedIp>,
+    buckets : Mutex<LruCache<BucketKey, Bucket>>
+}
+
+impl RateLimiter {
+    pub fn new(burst : u32, per_minute : u32, forwarded : Option<ForwardedIp>) -> Self {
+        Self {
+            burst: burst.max(1) as f64,
+            per_second: per_minute as f64 / 60.0,
+            forwarded,
+            buckets: Mutex::new(LruCache::new(MAX_BUCKETS))
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_second > 0.0
+    }
+
+    /// Takes a token from the bucket of `key`. Returns the seconds to wait before retrying when it's empty.
+    fn take(&self, key : BucketKey) -> Result<(), u64> {
+        self.take_at(key, Instant::now())
+    }
+
+    fn take_at(&self, key : BucketKey, now : Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains(&key) {
+            buckets.put(key.clone(), Bucket { tokens: self.burst, updated: now });
+        }
+
+        let bucket = buckets.get_mut(&key).unwrap();
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.per_second).ceil() as u64)
+        }
+    }
+}
+
+/// Where the reverse proxies in front record the address they received a request from, see `trust_forwarded`
+#[derive(Clone)]
+pub struct ForwardedIp {
+    /// Lowercase name of the header
+    header : String,
+    /// Amount of proxies in front that append to the header
+    hops : usize
+}
+
+impl ForwardedIp {
+    /// `None` unless `trust_forwarded` is set
+    pub fn from_conf(conf : &Config) -> Option<Self> {
+        if !conf.trust_forwarded {
+            return None;
+        }
+        Some(Self::new(&conf.forwarded_header, conf.forwarded_hops as usize))
+    }
+
+    fn new(header : &str, hops : usize) -> Self {
+        Self { header: header.to_lowercase(), hops: hops.max(1) }
+    }
+
+    /// Each proxy appends the address it received the request from, so only the last `hops` entries were written by
+    /// proxies. Anything further left came from the client and can't be trusted. `None` when the header has fewer
+    /// entries than that, or the entry isn't an IP address.
+    fn client_ip(&self, headers : &HeaderMap) -> Option<IpAddr> {
+        let entries : Vec<&str> = headers.get_all(self.header.as_str())
+            .filter_map(|val| val.to_str().ok())
+            .flat_map(|val| val.split(','))
+            .map(|val| val.trim())
+            .collect();
+        let entry = entries.len().checked_sub(self.hops).map(|idx| entries[idx])?;
+
+        if self.header == "forwarded" {
+            // RFC 7239 elements look like `for=192.0.2.60;proto=https`, with IPv6 addresses quoted and in brackets
+            entry.split(';')
+                .map(|pair| pair.trim())
+                .find(|pair| pair.len() > 4 && pair[..4].eq_ignore_ascii_case("for="))
+                .and_then(|pair| parse_ip(pair[4..].trim_matches('"')))
+        } else {
+            parse_ip(entry)
+        }
+    }
+}
+
+/// Parses an IP address that may come with a port, and IPv6 addresses in brackets
+fn parse_ip(val : &str) -> Option<IpAddr> {
+    val.parse::<IpAddr>().ok()
+        .or_else(|| val.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| val.strip_prefix('[').and_then(|val| val.strip_suffix(']')).and_then(|val| val.parse().ok()))
+}
+
+/// The address of the client making the request, for rate limiting and the audit log. With `forwarded`, which should
+/// only be set behind reverse proxies as the headers are trivial to spoof otherwise, it's taken from the entry the
+/// proxies appended, falling back to the peer address when there is none.
+pub fn client_ip(headers : &HeaderMap, peer_addr : Option<SocketAddr>, forwarded : Option<&ForwardedIp>) -> Option<String> {
+    forwarded.and_then(|forwarded| forwarded.client_ip(headers))
+        .or_else(|| peer_addr.map(|addr| addr.ip()))
+        .map(|ip| ip.to_string())
+}
+
+#[derive(Clone, Copy)]
+enum KeyBy {
+    ApiKey,
+    Ip
+}
+
+pub struct RateLimit {
+    limiter : Arc<RateLimiter>,
+    key_by : KeyBy
+}
+
+impl RateLimit {
+    /// Limits each API key separately. Has to be wrapped by `AuthMiddleware`, so the caller is known.
+    pub fn per_api_key(limiter : Arc<RateLimiter>) -> Self {
+        Self { limiter, key_by: KeyBy::ApiKey }
+    }
+
+    /// Limits each client IP separately
+    pub fn per_ip(limiter : Arc<RateLimiter>) -> Self {
+        Self { limiter, key_by: KeyBy::Ip }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service, limiter: self.limiter.clone(), key_by: self.key_by }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<RateLimiter>,
+    key_by: KeyBy
+}
+
+impl<S> RateLimitMiddleware<S> {
+    /// Callers that can't be told apart from others, such as on a Unix domain socket without `trust_forwarded`, all
+    /// share a single bucket, so the socket can't be used to get around the limit.
+    fn bucket_key(&self, request : &ServiceRequest) -> BucketKey {
+        if let KeyBy::ApiKey = self.key_by {
+            if let Some(principal) = request.extensions().get::<Principal>() {
+                return BucketKey::ApiKey(principal.key_id);
+            }
+        }
+
+        client_ip(request.headers(), request.peer_addr(), self.limiter.forwarded.as_ref())
+            .map_or(BucketKey::Unidentified, BucketKey::Ip)
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        if self.limiter.is_enabled() {
+            if let Err(retry_after) = self.limiter.take(self.bucket_key(&request)) {
+                let resp = HttpResponse::TooManyRequests()
+                    .insert_header(("Retry-After", retry_after.to_string()))
+                    .finish()
+                    .map_into_right_body();
+                let (request, _pl) = request.into_parts();
+                return Box::pin(async {
+                    Ok(ServiceResponse::new(request, resp))
+                });
+            }
+        }
+
+        let res = self.service.call(request);
+        return Box::pin(async {
+            res.await.map(ServiceResponse::map_into_left_body)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn ip(val : &str) -> BucketKey {
+        BucketKey::Ip(val.to_owned())
+    }
+
+    #[test]
+    fn allows_burst_then_limits() {
+        let limiter = RateLimiter::new(3, 60, None);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.take_at(ip("10.0.0.1"), now), Ok(()));
+        }
+        assert!(limiter.take_at(ip("10.0.0.1"), now).is_err());
+        // Other callers have buckets of their own
+        assert_eq!(limiter.take_at(ip("10.0.0.2"), now), Ok(()));
+        assert_eq!(limiter.take_at(BucketKey::ApiKey(1), now), Ok(()));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        // One token a second
+        let limiter = RateLimiter::new(2, 60, None);
+        let now = Instant::now();
+
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), now), Ok(()));
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), now), Ok(()));
+        assert!(limiter.take_at(ip("10.0.0.1"), now + Duration::from_millis(500)).is_err());
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), now + Duration::from_secs(1)), Ok(()));
+
+        // Refilling stops at the burst size
+        let later = now + Duration::from_secs(60);
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), later), Ok(()));
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), later), Ok(()));
+        assert!(limiter.take_at(ip("10.0.0.1"), later).is_err());
+    }
+
+    #[test]
+    fn retry_after_is_time_until_next_token() {
+        // One token every 10 seconds
+        let limiter = RateLimiter::new(1, 6, None);
+        let now = Instant::now();
+
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), now), Ok(()));
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), now), Err(10));
+        assert_eq!(limiter.take_at(ip("10.0.0.1"), now + Duration::from_millis(2500)), Err(8));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_bucket() {
+        let limiter = RateLimiter::new(1, 1, None);
+        let now = Instant::now();
+
+        assert_eq!(limiter.take_at(ip("first"), now), Ok(()));
+        for i in 0..MAX_BUCKETS {
+            assert_eq!(limiter.take_at(ip(&i.to_string()), now), Ok(()));
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_BUCKETS);
+        // The drained bucket of the first caller was dropped, so it starts over with a full one
+        assert_eq!(limiter.take_at(ip("first"), now), Ok(()));
+    }
+
+    fn headers(name : &str, values : &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for val in values {
+            headers.append(name.parse().unwrap(), val.parse().unwrap());
+        }
+        headers
+    }
+
+    fn peer() -> Option<SocketAddr> {
+        Some("10.0.0.9:50000".parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_unless_trusted() {
+        let headers = headers("x-forwarded-for", &["203.0.113.7"]);
+        assert_eq!(client_ip(&headers, peer(), None), Some("10.0.0.9".to_owned()));
+    }
+
+    #[test]
+    fn takes_entry_appended_by_proxy() {
+        let forwarded = ForwardedIp::new("X-Forwarded-For", 1);
+        // The client sent its own header, which the proxy appended the real address to
+        let spoofed = headers("x-forwarded-for", &["1.2.3.4, 5.6.7.8, 203.0.113.7"]);
+        assert_eq!(client_ip(&spoofed, peer(), Some(&forwarded)), Some("203.0.113.7".to_owned()));
+        // Same when the proxy adds a header line rather than appending to the existing one
+        let spoofed = headers("x-forwarded-for", &["1.2.3.4", "203.0.113.7"]);
+        assert_eq!(client_ip(&spoofed, peer(), Some(&forwarded)), Some("203.0.113.7".to_owned()));
+    }
+
+    #[test]
+    fn skips_trusted_hops() {
+        let forwarded = ForwardedIp::new("x-forwarded-for", 2);
+        let headers = headers("x-forwarded-for", &["1.2.3.4, 203.0.113.7, 10.0.0.2"]);
+        assert_eq!(client_ip(&headers, peer(), Some(&forwarded)), Some("203.0.113.7".to_owned()));
+
+        // Fewer entries than proxies, so none of them can be trusted
+        let headers = self::headers("x-forwarded-for", &["203.0.113.7"]);
+        assert_eq!(client_ip(&headers, peer(), Some(&forwarded)), Some("10.0.0.9".to_owned()));
+    }
+
+    #[test]
+    fn parses_forwarded_header() {
+        let forwarded = ForwardedIp::new("Forwarded", 1);
+        let headers = headers("forwarded", &["for=1.2.3.4", "for=\"[2001:db8::1]:4711\";proto=https"]);
+        assert_eq!(client_ip(&headers, peer(), Some(&forwarded)), Some("2001:db8::1".to_owned()));
+
+        // The other header is ignored, as the proxy doesn't set it
+        let spoofed = self::headers("x-forwarded-for", &["1.2.3.4"]);
+        assert_eq!(client_ip(&spoofed, peer(), Some(&forwarded)), Some("10.0.0.9".to_owned()));
+    }
+
+    #[test]
+    fn unix_socket_needs_trust_forwarded() {
+        // Connections over a Unix domain socket have no peer address
+        let headers = headers("x-forwarded-for", &["203.0.113.7"]);
+        assert_eq!(client_ip(&headers, None, None), None);
+        let forwarded = ForwardedIp::new("x-forwarded-for", 1);
+        assert_eq!(client_ip(&headers, None, Some(&forwarded)), Some("203.0.113.7".to_owned()));
+    }
+
+    #[test]
+    fn falls_back_to_peer_on_garbage() {
+        let forwarded = ForwardedIp::new("x-forwarded-for", 1);
+        let headers = headers("x-forwarded-for", &["1.2.3.4, unknown"]);
+        assert_eq!(client_ip(&headers, peer(), Some(&forwarded)), Some("10.0.0.9".to_owned()));
+    }
+}
\ No newline at end of file
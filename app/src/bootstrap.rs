@@ -0,0 +1,48 @@
+use log::info;
+use crate::config::Config;
+use crate::model::api_key::{generate_key, ApiKeyDbInsert, Scope};
+use crate::model::audit::AuditContext;
+use crate::storage::{Storage, StorageResult};
+
+/// Creates an admin key on first start, as every route that could create one already requires a key.
+/// Uses `bootstrap_key` from the config if set, otherwise a random key is generated and printed once.
+pub fn ensure_admin_key(storage : &dyn Storage, conf : &Config) -> StorageResult<()> {
+    if !conf.auth_mode.accepts_api_keys() {
+        return Ok(());
+    }
+    // Checked again by create_first_key, this only saves hashing a key on every start
+    if storage.count_keys()? > 0 {
+        if conf.bootstrap_key.is_some() {
+            info!("API keys already exist, ignoring bootstrap_key");
+        }
+        return Ok(());
+    }
+
+    let (key, generated) = match &conf.bootstrap_key {
+        Some(key) => (key.clone(), false),
+        None => (generate_key(), true)
+    };
+    let entry = ApiKeyDbInsert::new(&key, Some("bootstrap".to_owned()), &Scope::ALL, None);
+    let key_id = match storage.create_first_key(&entry, &AuditContext::without_actor())? {
+        Some(val) => val,
+        // Another instance got there first
+        None => return Ok(())
+    };
+
+    if generated {
+        println!("No API keys exist yet, created admin API key {}:", key_id);
+        println!("{}", key);
+        println!("Store the key somewhere safe, it won't be shown again");
+    } else {
+        info!("Created admin API key {} from bootstrap_key", key_id);
+    }
+
+    Ok(())
+}
+
+/// Creates a key with a random secret, returning its id and the secret
+pub fn mint_key(storage : &dyn Storage, description : Option<String>, scopes : &[Scope]) -> StorageResult<(i64, String)> {
+    let key = generate_key();
+    let key_id = storage.create_key(&ApiKeyDbInsert::new(&key, description, scopes, None), &AuditContext::without_actor())?;
+    Ok((key_id, key))
+}
\ No newline at end of file
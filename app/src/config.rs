@@ -1,20 +1,496 @@
 use config::ConfigError;
 use crate::model::db::{DATABASE_URL, get_db_path};
-use serde::{Serialize, Deserialize};
+use crate::model::url::{is_valid_redirect_type, REDIRECT_TYPES};
+use serde::{Serialize, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use crate::model::api_key::Scope;
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub const BOOTSTRAP_KEY_MIN_LEN : usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
-    pub hostname : String
+    pub hostname : String,
+    /// Addresses to accept connections on, see `Listener`. Given as a list, or in the environment as a comma
+    /// separated string.
+    #[serde(default = "default_listen", deserialize_with = "deserialize_list")]
+    pub listen : Vec<String>,
+    /// Port of the `listen` addresses that don't specify their own
+    #[serde(default = "default_port")]
+    pub port : u16,
+    /// Amount of worker threads handling requests. Defaults to the amount of physical CPU cores.
+    #[serde(default)]
+    pub workers : Option<usize>,
+    /// How long, in seconds, an idle connection is kept open for further requests. 0 disables keep-alive.
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive : u64,
+    /// Maximum amount of connections waiting to be accepted
+    #[serde(default = "default_backlog")]
+    pub backlog : u32,
+    /// Maximum size, in bytes, of request bodies
+    #[serde(default = "default_max_payload")]
+    pub max_payload : usize,
+    /// PEM file with the certificate chain to serve over TLS. The `tls_listen` addresses are only listened on when
+    /// both `tls_cert` and `tls_key` are set.
+    #[serde(default)]
+    pub tls_cert : Option<String>,
+    /// PEM file with the private key of `tls_cert`, in PKCS#8, PKCS#1 or SEC1 format
+    #[serde(default)]
+    pub tls_key : Option<String>,
+    /// Further certificates, served to clients asking for one of their `server_names` through SNI. Clients asking
+    /// for any other name get `tls_cert`.
+    #[serde(default)]
+    pub tls_sni : Vec<TlsSniCert>,
+    /// Addresses to accept TLS connections on. Same as `listen`, except Unix domain sockets aren't supported.
+    #[serde(default = "default_tls_listen", deserialize_with = "deserialize_list")]
+    pub tls_listen : Vec<String>,
+    /// Port of the `tls_listen` addresses that don't specify their own
+    #[serde(default = "default_tls_port")]
+    pub tls_port : u16,
+    /// How often, in seconds, the certificate and key files are checked for changes, so renewed certificates are
+    /// served without a restart
+    #[serde(default = "default_tls_reload_interval")]
+    pub tls_reload_interval : u64,
+    /// Database to store links and keys in. `postgres://` and `postgresql://` URLs use PostgreSQL, which lets several
+    /// instances share the data, anything else is the path of a SQLite database file. Defaults to `db` in the data dir.
+    #[serde(default = "default_database_url")]
+    pub database_url : String,
+    /// How often, in seconds, expired links are swept from the database
+    #[serde(default = "default_expired_sweep_interval")]
+    pub expired_sweep_interval : u64,
+    /// Redirect status code used for links that don't specify their own
+    #[serde(default = "default_redirect_type")]
+    pub default_redirect_type : u16,
+    /// Amount of links kept in the redirect cache. 0 disables the cache.
+    #[serde(default = "default_link_cache_size")]
+    pub link_cache_size : usize,
+    /// How long, in seconds, a link is served from the redirect cache before it is looked up again. 0 disables the
+    /// cache.
+    #[serde(default = "default_link_cache_ttl")]
+    pub link_cache_ttl : u64,
+    /// How long, in seconds, a short id that doesn't exist is remembered, so repeated requests for it don't reach the
+    /// database. 0 disables caching of unknown ids.
+    #[serde(default = "default_link_cache_negative_ttl")]
+    pub link_cache_negative_ttl : u64,
+    /// How often, in seconds, the last time API keys were used is written to the database
+    #[serde(default = "default_key_last_used_flush_interval")]
+    pub key_last_used_flush_interval : u64,
+    /// How long, in seconds, a validated API key is remembered before it is checked against the database again.
+    /// 0 disables the cache.
+    ///
+    /// A key deleted or rotated through one instance is only dropped from that instance's cache. When several
+    /// instances share a PostgreSQL database, the others keep accepting the key for up to this long, so keep it short
+    /// or set it to 0 there.
+    #[serde(default = "default_key_cache_ttl")]
+    pub key_cache_ttl : u64,
+    /// Which header wins when a request has both `x-api-key` and `Authorization: Bearer`
+    #[serde(default)]
+    pub auth_header_precedence : AuthHeaderPrecedence,
+    /// Which credentials are accepted: API keys, JWTs issued by an OIDC provider, or both
+    #[serde(default)]
+    pub auth_mode : AuthMode,
+    /// Path or http(s) URL of the JWKS used to verify JWTs. Required unless `auth_mode` is `api-key`.
+    #[serde(default)]
+    pub jwt_jwks : Option<String>,
+    /// How often, in seconds, a JWKS fetched from a URL is fetched again
+    #[serde(default = "default_jwt_jwks_refresh_interval")]
+    pub jwt_jwks_refresh_interval : u64,
+    /// Required `iss` of JWTs
+    #[serde(default)]
+    pub jwt_issuer : Option<String>,
+    /// Required `aud` of JWTs
+    #[serde(default)]
+    pub jwt_audience : Option<String>,
+    /// Allowed clock skew, in seconds, when checking the expiry of JWTs
+    #[serde(default = "default_jwt_leeway")]
+    pub jwt_leeway : u64,
+    /// Claim holding the permissions of the caller, either as a space separated string or an array
+    #[serde(default = "default_jwt_scopes_claim")]
+    pub jwt_scopes_claim : String,
+    /// Maps values of `jwt_scopes_claim`, such as groups, to the scopes they grant. Values that aren't mapped are
+    /// used as is when they name a scope.
+    #[serde(default)]
+    pub jwt_scope_mapping : HashMap<String, Vec<Scope>>,
+    /// Admin key to create on first start, while there are no API keys yet. A random one is generated and printed
+    /// when left out.
+    #[serde(default)]
+    pub bootstrap_key : Option<String>,
+    /// Requests an API key may make to /new, /delete and /key in a burst, before being limited to
+    /// `rate_limit_api_per_minute`
+    #[serde(default = "default_rate_limit_api_burst")]
+    pub rate_limit_api_burst : u32,
+    /// Sustained requests an API key may make to /new, /delete and /key per minute. 0 disables the limit.
+    #[serde(default = "default_rate_limit_api_per_minute")]
+    pub rate_limit_api_per_minute : u32,
+    /// Redirects a client IP may request in a burst, before being limited to `rate_limit_redirect_per_minute`
+    #[serde(default = "default_rate_limit_redirect_burst")]
+    pub rate_limit_redirect_burst : u32,
+    /// Sustained redirects a client IP may request per minute. 0, the default, disables the limit.
+    ///
+    /// Behind a reverse proxy or load balancer every request comes from the proxy's address, so all clients would
+    /// share one limit. Set `trust_forwarded` there before enabling this.
+    #[serde(default = "default_rate_limit_redirect_per_minute")]
+    pub rate_limit_redirect_per_minute : u32,
+    /// QR codes a client IP may request in a burst, before being limited to `rate_limit_qr_per_minute`
+    #[serde(default = "default_rate_limit_qr_burst")]
+    pub rate_limit_qr_burst : u32,
+    /// Sustained QR codes a client IP may request per minute. 0 disables the limit. Rendering is expensive and needs
+    /// no API key, so this is on by default. Behind a reverse proxy it needs `trust_forwarded`, see
+    /// `rate_limit_redirect_per_minute`.
+    #[serde(default = "default_rate_limit_qr_per_minute")]
+    pub rate_limit_qr_per_minute : u32,
+    /// Take the client IP from `forwarded_header`, for rate limiting and the audit log. Only enable behind a reverse
+    /// proxy that sets this header. Requests whose client IP isn't known, such as over a Unix domain socket without
+    /// this, share a single per-IP limit.
+    #[serde(default)]
+    pub trust_forwarded : bool,
+    /// Header the reverse proxy records the client IP in with `trust_forwarded`. `X-Forwarded-For` by default, or
+    /// `Forwarded` for proxies following RFC 7239.
+    #[serde(default = "default_forwarded_header")]
+    pub forwarded_header : String,
+    /// Amount of reverse proxies in front that append to `forwarded_header`. The client IP is taken this many entries
+    /// from the right, as entries further left are sent by the client and can be spoofed.
+    #[serde(default = "default_forwarded_hops")]
+    pub forwarded_hops : u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthHeaderPrecedence {
+    #[serde(rename = "api-key")]
+    ApiKey,
+    #[serde(rename = "bearer")]
+    Bearer
+}
+
+impl Default for AuthHeaderPrecedence {
+    fn default() -> Self {
+        AuthHeaderPrecedence::ApiKey
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMode {
+    #[serde(rename = "api-key")]
+    ApiKey,
+    #[serde(rename = "jwt")]
+    Jwt,
+    #[serde(rename = "both")]
+    Both
+}
+
+impl AuthMode {
+    pub fn accepts_api_keys(&self) -> bool {
+        *self != AuthMode::Jwt
+    }
+
+    pub fn accepts_jwts(&self) -> bool {
+        *self != AuthMode::ApiKey
+    }
+}
+
+impl Default for AuthMode {
+    fn default() -> Self {
+        AuthMode::ApiKey
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TlsSniCert {
+    pub server_names : Vec<String>,
+    pub cert : String,
+    pub key : String
+}
+
+/// Where the server accepts connections, parsed from an entry of `listen`. Entries are either an IPv4 or IPv6
+/// address, optionally with a port (`127.0.0.1:8080`, `[::1]:8080`), or `unix:` followed by the path of a Unix
+/// domain socket. Requests over a Unix domain socket have no client IP unless `trust_forwarded` is set, see
+/// `client_ip`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listener {
+    Tcp(SocketAddr),
+    Unix(PathBuf)
+}
+
+impl Listener {
+    pub fn parse(entry : &str, default_port : u16) -> Result<Self, String> {
+        let entry = entry.trim();
+        if let Some(path) = entry.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: listener needs a socket path".to_owned());
+            }
+            return Ok(Listener::Unix(PathBuf::from(path)));
+        }
+
+        if let Ok(addr) = SocketAddr::from_str(entry) {
+            return Ok(Listener::Tcp(addr));
+        }
+        // IPv6 addresses without a port may be given with or without brackets
+        let ip = entry.strip_prefix('[').and_then(|val| val.strip_suffix(']')).unwrap_or(entry);
+        IpAddr::from_str(ip)
+            .map(|ip| Listener::Tcp(SocketAddr::new(ip, default_port)))
+            .map_err(|_| format!("Invalid listen address '{}'. Expected an IP address, optionally with a port, or unix:<path>", entry))
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listener::Tcp(addr) => write!(f, "{}", addr),
+            Listener::Unix(path) => write!(f, "unix:{}", path.display())
+        }
+    }
+}
+
+impl Config {
+    pub fn listeners(&self) -> Result<Vec<Listener>, String> {
+        self.listen.iter()
+            .map(|entry| Listener::parse(entry, self.port))
+            .collect()
+    }
+
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
+    /// The `tls_listen` addresses, or none when TLS isn't enabled
+    pub fn tls_listeners(&self) -> Result<Vec<SocketAddr>, String> {
+        if !self.tls_enabled() {
+            return Ok(Vec::new());
+        }
+
+        self.tls_listen.iter()
+            .map(|entry| match Listener::parse(entry, self.tls_port)? {
+                Listener::Tcp(addr) => Ok(addr),
+                Listener::Unix(_) => Err(format!("tls_listen can't use Unix domain sockets, got '{}'", entry))
+            })
+            .collect()
+    }
+}
+
+/// Accepts either a list of strings or a single comma separated string, as lists can't be given through the
+/// environment
+fn deserialize_list<'de, D : Deserializer<'de>>(deserializer : D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>)
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(val) => val.split(',')
+            .map(|entry| entry.trim().to_owned())
+            .filter(|entry| !entry.is_empty())
+            .collect(),
+        StringOrList::List(val) => val
+    })
+}
+
+pub fn default_listen() -> Vec<String> {
+    return vec!["0.0.0.0".to_owned()]
+}
+
+pub fn default_port() -> u16 {
+    return 8380
+}
+
+pub fn default_keep_alive() -> u64 {
+    return 5
+}
+
+pub fn default_backlog() -> u32 {
+    return 2048
+}
+
+pub fn default_max_payload() -> usize {
+    return 256 * 1024
+}
+
+pub fn default_tls_listen() -> Vec<String> {
+    return vec!["0.0.0.0".to_owned()]
+}
+
+pub fn default_tls_port() -> u16 {
+    return 8443
+}
+
+pub fn default_tls_reload_interval() -> u64 {
+    return 60
+}
+
+pub fn default_database_url() -> String {
+    return format!("{}/{}", get_db_path(), DATABASE_URL)
+}
+
+pub fn default_expired_sweep_interval() -> u64 {
+    return 300
+}
+
+pub fn default_redirect_type() -> u16 {
+    return 307
+}
+
+pub fn default_link_cache_size() -> usize {
+    return 10_000
+}
+
+pub fn default_link_cache_ttl() -> u64 {
+    return 60
+}
+
+pub fn default_link_cache_negative_ttl() -> u64 {
+    return 10
+}
+
+pub fn default_key_last_used_flush_interval() -> u64 {
+    return 60
+}
+
+pub fn default_key_cache_ttl() -> u64 {
+    return 30
+}
+
+pub fn default_jwt_jwks_refresh_interval() -> u64 {
+    return 3600
+}
+
+pub fn default_jwt_leeway() -> u64 {
+    return 60
+}
+
+pub fn default_jwt_scopes_claim() -> String {
+    return "scope".to_owned()
+}
+
+pub fn default_rate_limit_api_burst() -> u32 {
+    return 30
+}
+
+pub fn default_rate_limit_api_per_minute() -> u32 {
+    return 60
+}
+
+pub fn default_rate_limit_redirect_burst() -> u32 {
+    return 100
+}
+
+pub fn default_rate_limit_redirect_per_minute() -> u32 {
+    return 0
+}
+
+pub fn default_rate_limit_qr_burst() -> u32 {
+    return 20
+}
+
+pub fn default_rate_limit_qr_per_minute() -> u32 {
+    return 60
+}
+
+pub fn default_forwarded_header() -> String {
+    return "X-Forwarded-For".to_owned()
+}
+
+pub fn default_forwarded_hops() -> u32 {
+    return 1
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hostname: String::new(),
+            listen: default_listen(),
+            port: default_port(),
+            workers: None,
+            keep_alive: default_keep_alive(),
+            backlog: default_backlog(),
+            max_payload: default_max_payload(),
+            tls_cert: None,
+            tls_key: None,
+            tls_sni: Vec::new(),
+            tls_listen: default_tls_listen(),
+            tls_port: default_tls_port(),
+            tls_reload_interval: default_tls_reload_interval(),
+            database_url: default_database_url(),
+            expired_sweep_interval: default_expired_sweep_interval(),
+            default_redirect_type: default_redirect_type(),
+            link_cache_size: default_link_cache_size(),
+            link_cache_ttl: default_link_cache_ttl(),
+            link_cache_negative_ttl: default_link_cache_negative_ttl(),
+            key_last_used_flush_interval: default_key_last_used_flush_interval(),
+            key_cache_ttl: default_key_cache_ttl(),
+            auth_header_precedence: AuthHeaderPrecedence::default(),
+            auth_mode: AuthMode::default(),
+            jwt_jwks: None,
+            jwt_jwks_refresh_interval: default_jwt_jwks_refresh_interval(),
+            jwt_issuer: None,
+            jwt_audience: None,
+            jwt_leeway: default_jwt_leeway(),
+            jwt_scopes_claim: default_jwt_scopes_claim(),
+            jwt_scope_mapping: HashMap::new(),
+            bootstrap_key: None,
+            rate_limit_api_burst: default_rate_limit_api_burst(),
+            rate_limit_api_per_minute: default_rate_limit_api_per_minute(),
+            rate_limit_redirect_burst: default_rate_limit_redirect_burst(),
+            rate_limit_redirect_per_minute: default_rate_limit_redirect_per_minute(),
+            rate_limit_qr_burst: default_rate_limit_qr_burst(),
+            rate_limit_qr_per_minute: default_rate_limit_qr_per_minute(),
+            trust_forwarded: false,
+            forwarded_header: default_forwarded_header(),
+            forwarded_hops: default_forwarded_hops()
+        }
+    }
 }
 
 pub fn load_conf() -> Result<Config, ConfigError> {
+    // Later sources take precedence, so environment variables override the config file
     let settings = config::Config::builder()
-        .add_source(config::Environment::with_prefix("URL"))
         .add_source(config::File::with_name(format!("{}/{}", get_db_path(), "config.yaml").as_str()).required(false))
+        .add_source(config::Environment::with_prefix("URL"))
         .build()
         .unwrap();
 
     let config : Config = settings.try_deserialize()?;
 
+    if config.tls_cert.is_some() != config.tls_key.is_some() {
+        return Err(ConfigError::Message("tls_cert and tls_key have to be set together".to_owned()));
+    }
+
+    if !config.tls_sni.is_empty() && !config.tls_enabled() {
+        return Err(ConfigError::Message("tls_sni requires tls_cert and tls_key".to_owned()));
+    }
+
+    let listeners = config.listeners().map_err(ConfigError::Message)?;
+    let tls_listeners = config.tls_listeners().map_err(ConfigError::Message)?;
+    if listeners.is_empty() && tls_listeners.is_empty() {
+        return Err(ConfigError::Message("listen or tls_listen needs at least one address".to_owned()));
+    }
+
+    if actix_web::http::header::HeaderName::from_str(&config.forwarded_header).is_err() {
+        return Err(ConfigError::Message(format!("forwarded_header '{}' isn't a valid header name", config.forwarded_header)));
+    }
+
+    if config.forwarded_hops == 0 {
+        return Err(ConfigError::Message("forwarded_hops must be at least 1".to_owned()));
+    }
+
+    if config.workers == Some(0) {
+        return Err(ConfigError::Message("workers must be at least 1".to_owned()));
+    }
+
+    if !is_valid_redirect_type(config.default_redirect_type) {
+        return Err(ConfigError::Message(format!("default_redirect_type must be one of {:?}", REDIRECT_TYPES)));
+    }
+
+    if config.bootstrap_key.as_ref().map_or(false, |key| key.len() < BOOTSTRAP_KEY_MIN_LEN) {
+        return Err(ConfigError::Message(format!("bootstrap_key must be at least {} characters long", BOOTSTRAP_KEY_MIN_LEN)));
+    }
+
+    if config.auth_mode.accepts_jwts() && (config.jwt_jwks.is_none() || config.jwt_issuer.is_none() || config.jwt_audience.is_none()) {
+        return Err(ConfigError::Message("jwt_jwks, jwt_issuer and jwt_audience are required to accept JWTs".to_owned()));
+    }
+
     Ok(config)
 }
\ No newline at end of file
@@ -1,4 +1,6 @@
+use clap::{arg, command, Command};
 use log::info;
+use std::str::FromStr;
 
 #[macro_use]
 extern crate diesel;
@@ -10,24 +12,61 @@ mod model;
 mod api;
 mod web;
 mod schema;
-
-use diesel_migrations::{embed_migrations};
-
-embed_migrations!("../migrations");
+mod qr;
+mod tasks;
+mod templates;
+mod bootstrap;
+mod storage;
+mod tls;
 
 
 fn main() {
+    let matches = command!()
+        .subcommand(
+            Command::new("mint-key")
+                .about("Create an API key directly in the database, without going through the API")
+                .arg(arg!(-d --description <DESCRIPTION> "Sets description of the created API key").required(false))
+                .arg(arg!(-s --scope <SCOPE> "Grants a scope to the created API key. Can be repeated. Defaults to all scopes").required(false).multiple_occurrences(true))
+        )
+        .get_matches();
+
     setup_tracing(Some(tracing::Level::TRACE));
     let app_version = env!("CARGO_PKG_VERSION");
     info!("Launching url v{} service", app_version);
 
+    let conf = config::load_conf().expect("Unable to load config");
+
     // db setup
-    {
-        let conn = model::db::get_db_conn().unwrap();
-        embedded_migrations::run_with_output(&conn, &mut std::io::stdout());
+    let storage = storage::open(&conf.database_url).expect("Unable to connect to the database");
+    storage.run_migrations().expect("Unable to run migrations");
+    let hashed = storage.hash_legacy_keys().expect("Unable to hash plaintext API keys");
+    if hashed > 0 {
+        info!("Hashed {} API key(s) that were stored in plaintext", hashed);
     }
 
-    web::start_server();
+    if let Some(("mint-key", sub_matches)) = matches.subcommand() {
+        let scopes = match sub_matches.values_of("scope") {
+            Some(vals) => match vals.map(model::api_key::Scope::from_str).collect::<Result<Vec<_>, _>>() {
+                Ok(val) => val,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            },
+            None => model::api_key::Scope::ALL.to_vec()
+        };
+        let description = sub_matches.value_of("description").map(|val| val.to_owned());
+
+        let (key_id, key) = bootstrap::mint_key(storage.as_ref(), description, &scopes).expect("Unable to create API key");
+        println!("ID: {}", key_id);
+        println!("Key: {}", key);
+        println!("Store the key somewhere safe, it can't be shown again");
+        return;
+    }
+
+    bootstrap::ensure_admin_key(storage.as_ref(), &conf).expect("Unable to create bootstrap API key");
+
+    web::start_server(storage, conf);
 }
 
 fn setup_tracing(log_level : Option<tracing::Level>) {
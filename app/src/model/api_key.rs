@@ -1,26 +1,128 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
 use db::api_keys;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Amount of leading characters of a key stored in plaintext, used to look up the key without revealing it
+pub const KEY_PREFIX_LEN : usize = 8;
+
+nano_id::gen!(
+    api_key,
+    86,
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ&*/@!#$%^()-_=+[]{};:,.?"
+);
+
+/// Permission granted to an API key
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "links:read")]
+    LinksRead,
+    #[serde(rename = "links:write")]
+    LinksWrite,
+    #[serde(rename = "links:delete")]
+    LinksDelete,
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    #[serde(rename = "keys:admin")]
+    KeysAdmin,
+    /// Allows managing links created by other keys
+    #[serde(rename = "links:admin")]
+    LinksAdmin,
+}
+
+impl Scope {
+    pub const ALL : [Scope; 6] = [Scope::LinksRead, Scope::LinksWrite, Scope::LinksDelete, Scope::StatsRead, Scope::KeysAdmin, Scope::LinksAdmin];
+    /// Scopes given to new keys when none are requested. Administrative scopes have to be asked for explicitly.
+    pub const DEFAULT : [Scope; 4] = [Scope::LinksRead, Scope::LinksWrite, Scope::LinksDelete, Scope::StatsRead];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::LinksRead => "links:read",
+            Scope::LinksWrite => "links:write",
+            Scope::LinksDelete => "links:delete",
+            Scope::StatsRead => "stats:read",
+            Scope::KeysAdmin => "keys:admin",
+            Scope::LinksAdmin => "links:admin",
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL.iter()
+            .find(|scope| scope.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown scope '{}'. Valid scopes are: {}", s, join_scopes(&Scope::ALL)))
+    }
+}
+
+/// Parses a comma separated list of scopes as stored in the database, skipping unknown entries
+pub fn parse_scopes(input : &str) -> Vec<Scope> {
+    input.split(',')
+        .filter_map(|val| Scope::from_str(val.trim()).ok())
+        .collect()
+}
+
+pub fn join_scopes(scopes : &[Scope]) -> String {
+    scopes.iter().map(|scope| scope.as_str()).collect::<Vec<&str>>().join(",")
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct ApiKey {
     pub id : i64,
-    pub key : String,
-    pub description : Option<String>
+    pub key_prefix : String,
+    pub description : Option<String>,
+    pub scopes : Vec<Scope>,
+    pub expires_at : Option<NaiveDateTime>,
+    pub created_at : NaiveDateTime,
+    pub last_used_at : Option<NaiveDateTime>,
+    /// Set for the records of callers authenticated with a JWT, which have no secret of their own
+    pub subject : Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ApiKeyPostRequest {
-    pub description : Option<String>
+    pub description : Option<String>,
+    /// Defaults to `Scope::DEFAULT` when left out
+    pub scopes : Option<Vec<Scope>>,
+    pub expires_at : Option<DateTime<Utc>>,
+    pub ttl_seconds : Option<i64>
+}
+
+impl ApiKeyPostRequest {
+    /// Resolves the absolute expiry of the key, if any.
+    pub fn expiry(&self, now : NaiveDateTime) -> Result<Option<NaiveDateTime>, String> {
+        crate::model::url::resolve_expiry(self.expires_at, self.ttl_seconds, now)
+    }
 }
 
+/// Returned once when a key is created. The key itself can't be retrieved afterwards.
 #[derive(Serialize, Deserialize)]
 pub struct ApiKeyPostResponse {
+    pub id : i64,
     pub key : String
 }
 
+#[derive(Serialize, Deserialize, Default)]
+pub struct ApiKeyRotateRequest {
+    /// How long the old secret keeps working after the rotation, greater than zero and at most
+    /// `MAX_OFFSET_SECONDS`. It stops working immediately when left out.
+    pub grace_seconds : Option<i64>
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ApiKeyDeleteRequest {
-    pub key : String
+    pub id : i64
 }
 
 
@@ -28,14 +130,19 @@ impl From<ApiKeyDb> for ApiKey {
     fn from(u: ApiKeyDb) -> Self {
         Self {
             id: u.id,
+            key_prefix: u.key_prefix,
             description: u.description,
-            key: u.key
+            scopes: parse_scopes(&u.scopes),
+            expires_at: u.expires_at,
+            created_at: u.created_at,
+            last_used_at: u.last_used_at,
+            subject: u.subject
         }
     }
 }
 impl PartialEq for ApiKey {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id && self.key == other.key
+        self.id == other.id && self.key_prefix == other.key_prefix
     }
 }
 
@@ -43,16 +150,47 @@ impl PartialEq for ApiKey {
 #[table_name="api_keys"]
 pub struct ApiKeyDb {
     pub id : i64,
-    pub key : String,
-    pub description : Option<String>
+    pub key_prefix : String,
+    pub key_hash : String,
+    pub key_salt : String,
+    pub legacy_key : Option<String>,
+    pub description : Option<String>,
+    pub scopes : String,
+    pub expires_at : Option<NaiveDateTime>,
+    pub created_at : NaiveDateTime,
+    pub last_used_at : Option<NaiveDateTime>,
+    pub previous_key_prefix : Option<String>,
+    pub previous_key_hash : Option<String>,
+    pub previous_key_salt : Option<String>,
+    pub previous_key_expires_at : Option<NaiveDateTime>,
+    pub subject : Option<String>
 }
 
-impl From<ApiKey> for ApiKeyDb {
-    fn from(u: ApiKey) -> Self {
-        Self {
-            id: u.id,
-            description: u.description,
-            key: u.key
+impl ApiKeyDb {
+    pub fn is_expired(&self, now : NaiveDateTime) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+
+    pub fn scopes(&self) -> Vec<Scope> {
+        parse_scopes(&self.scopes)
+    }
+
+    /// Checks the given plaintext key against the stored hash
+    pub fn verify(&self, key : &str) -> bool {
+        if self.key_hash.is_empty() {
+            return false;
+        }
+        constant_time_eq(hash_key(key, &self.key_salt).as_bytes(), self.key_hash.as_bytes())
+    }
+
+    /// Checks the given plaintext key against the secret replaced by the last rotation, if its grace period is still
+    /// running
+    pub fn verify_previous(&self, key : &str, now : NaiveDateTime) -> bool {
+        match (&self.previous_key_hash, &self.previous_key_salt, self.previous_key_expires_at) {
+            (Some(prev_hash), Some(prev_salt), Some(prev_expires_at)) if prev_expires_at > now => {
+                constant_time_eq(hash_key(key, prev_salt).as_bytes(), prev_hash.as_bytes())
+            },
+            _ => false
         }
     }
 }
@@ -60,8 +198,105 @@ impl From<ApiKey> for ApiKeyDb {
 #[derive(Insertable)]
 #[table_name="api_keys"]
 pub struct ApiKeyDbInsert {
-    pub key : String,
-    pub description : Option<String>
+    pub key_prefix : String,
+    pub key_hash : String,
+    pub key_salt : String,
+    pub description : Option<String>,
+    pub scopes : String,
+    pub expires_at : Option<NaiveDateTime>,
+    pub created_at : NaiveDateTime
+}
+
+impl ApiKeyDbInsert {
+    pub fn new(key : &str, description : Option<String>, scopes : &[Scope], expires_at : Option<NaiveDateTime>) -> Self {
+        let salt = new_salt();
+        Self {
+            key_prefix: key_prefix(key),
+            key_hash: hash_key(key, &salt),
+            key_salt: salt,
+            description,
+            scopes: join_scopes(scopes),
+            expires_at,
+            created_at: Utc::now().naive_utc()
+        }
+    }
+}
+
+/// Record of a JWT subject. The key hash is left empty, so it can't be used as an API key.
+#[derive(Insertable)]
+#[table_name="api_keys"]
+pub struct ApiKeyDbSubjectInsert {
+    pub key_prefix : String,
+    pub key_hash : String,
+    pub key_salt : String,
+    pub scopes : String,
+    pub created_at : NaiveDateTime,
+    pub subject : String
+}
+
+/// Replacement secret of an existing key, see `ApiKeyRotateRequest`
+#[derive(AsChangeset)]
+#[table_name="api_keys"]
+#[changeset_options(treat_none_as_null="true")]
+pub struct ApiKeyDbRotate {
+    pub key_prefix : String,
+    pub key_hash : String,
+    pub key_salt : String,
+    pub legacy_key : Option<String>,
+    pub previous_key_prefix : Option<String>,
+    pub previous_key_hash : Option<String>,
+    pub previous_key_salt : Option<String>,
+    pub previous_key_expires_at : Option<NaiveDateTime>
+}
+
+impl ApiKeyDbRotate {
+    /// Swaps in `key` as the secret of `current`. With a grace period the current secret is kept around until it ends.
+    pub fn new(key : &str, current : &ApiKeyDb, grace_until : Option<NaiveDateTime>) -> Self {
+        let salt = new_salt();
+        let (previous_key_prefix, previous_key_hash, previous_key_salt) = match grace_until {
+            Some(_) => (Some(current.key_prefix.clone()), Some(current.key_hash.clone()), Some(current.key_salt.clone())),
+            None => (None, None, None)
+        };
+        Self {
+            key_prefix: key_prefix(key),
+            key_hash: hash_key(key, &salt),
+            key_salt: salt,
+            legacy_key: None,
+            previous_key_prefix,
+            previous_key_hash,
+            previous_key_salt,
+            previous_key_expires_at: grace_until
+        }
+    }
+}
+
+/// Generates a new random API key
+pub fn generate_key() -> String {
+    api_key::<64>()
+}
+
+pub fn key_prefix(key : &str) -> String {
+    key.chars().take(KEY_PREFIX_LEN).collect()
+}
+
+pub fn hash_key(key : &str, salt : &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(key.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn new_salt() -> String {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).expect("Unable to generate salt");
+    hex::encode(salt)
+}
+
+fn constant_time_eq(a : &[u8], b : &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 // Manually maintained instead of using the autogenerated schema.rs
@@ -74,8 +309,143 @@ pub mod db {
     table! {
     api_keys (id) {
         id -> BigInt,
-        key -> Text,
+        key_prefix -> Text,
+        key_hash -> Text,
+        key_salt -> Text,
+        legacy_key -> Nullable<Text>,
         description -> Nullable<Text>,
+        scopes -> Text,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        previous_key_prefix -> Nullable<Text>,
+        previous_key_hash -> Nullable<Text>,
+        previous_key_salt -> Nullable<Text>,
+        previous_key_expires_at -> Nullable<Timestamp>,
+        subject -> Nullable<Text>,
     }
 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::from_timestamp(1_660_000_000, 0)
+    }
+
+    /// A stored key whose secret is `key`, as created by `ApiKeyDbInsert::new`
+    fn entry(key : &str) -> ApiKeyDb {
+        let insert = ApiKeyDbInsert::new(key, None, &Scope::DEFAULT, None);
+        ApiKeyDb {
+            id: 1,
+            key_prefix: insert.key_prefix,
+            key_hash: insert.key_hash,
+            key_salt: insert.key_salt,
+            legacy_key: None,
+            description: None,
+            scopes: insert.scopes,
+            expires_at: None,
+            created_at: insert.created_at,
+            last_used_at: None,
+            previous_key_prefix: None,
+            previous_key_hash: None,
+            previous_key_salt: None,
+            previous_key_expires_at: None,
+            subject: None
+        }
+    }
+
+    /// Applies a rotation to `entry` the way the database would
+    fn rotate(entry : &ApiKeyDb, key : &str, grace_until : Option<NaiveDateTime>) -> ApiKeyDb {
+        let rotate = ApiKeyDbRotate::new(key, entry, grace_until);
+        ApiKeyDb {
+            key_prefix: rotate.key_prefix,
+            key_hash: rotate.key_hash,
+            key_salt: rotate.key_salt,
+            legacy_key: rotate.legacy_key,
+            previous_key_prefix: rotate.previous_key_prefix,
+            previous_key_hash: rotate.previous_key_hash,
+            previous_key_salt: rotate.previous_key_salt,
+            previous_key_expires_at: rotate.previous_key_expires_at,
+            ..entry.clone()
+        }
+    }
+
+    #[test]
+    fn hash_depends_on_salt() {
+        assert_eq!(hash_key("secret", "salt"), hash_key("secret", "salt"));
+        assert_ne!(hash_key("secret", "salt"), hash_key("secret", "pepper"));
+        assert_ne!(hash_key("secret", "salt"), hash_key("Secret", "salt"));
+        // Keys with the same secret don't end up with the same hash
+        assert_ne!(entry("secret").key_hash, entry("secret").key_hash);
+    }
+
+    #[test]
+    fn verifies_matching_key() {
+        let key = generate_key();
+        let entry = entry(&key);
+        assert_eq!(entry.key_prefix, key[..KEY_PREFIX_LEN]);
+        assert!(entry.verify(&key));
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        let key = generate_key();
+        let entry = entry(&key);
+        assert!(!entry.verify(&generate_key()));
+        assert!(!entry.verify(&key[..key.len() - 1]));
+        assert!(!entry.verify(""));
+        // The stored hash itself isn't accepted as the key
+        assert!(!entry.verify(&entry.key_hash.clone()));
+    }
+
+    #[test]
+    fn empty_hash_is_never_accepted() {
+        // Plaintext keys from before hashing, and the records of JWT subjects, have no hash
+        let mut legacy = entry("legacy-key");
+        legacy.key_hash = String::new();
+        legacy.legacy_key = Some("legacy-key".to_owned());
+        assert!(!legacy.verify("legacy-key"));
+        assert!(!legacy.verify(""));
+
+        // Once hashed the way `hash_legacy_keys` does, the same key is accepted
+        legacy.key_salt = new_salt();
+        legacy.key_hash = hash_key("legacy-key", &legacy.key_salt);
+        legacy.legacy_key = None;
+        assert!(legacy.verify("legacy-key"));
+    }
+
+    #[test]
+    fn previous_key_works_during_grace_period() {
+        let old = generate_key();
+        let new = generate_key();
+        let rotated = rotate(&entry(&old), &new, Some(now() + chrono::Duration::seconds(60)));
+
+        assert!(rotated.verify(&new));
+        assert!(!rotated.verify(&old));
+        assert!(rotated.verify_previous(&old, now()));
+        assert!(!rotated.verify_previous(&new, now()));
+        // The grace period ends at `previous_key_expires_at`
+        assert!(!rotated.verify_previous(&old, now() + chrono::Duration::seconds(60)));
+        assert!(!rotated.verify_previous(&old, now() + chrono::Duration::seconds(61)));
+    }
+
+    #[test]
+    fn previous_key_stops_without_grace_period() {
+        let old = generate_key();
+        let new = generate_key();
+        let rotated = rotate(&entry(&old), &new, None);
+
+        assert!(rotated.verify(&new));
+        assert!(!rotated.verify(&old));
+        assert!(!rotated.verify_previous(&old, now()));
+    }
+
+    #[test]
+    fn huge_ttl_is_rejected() {
+        let req : ApiKeyPostRequest = serde_json::from_str(r#"{"ttl_seconds": 9223372036854775807}"#).unwrap();
+        assert!(req.expiry(now()).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,134 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::schema::{audit_log};
+use serde::{Serialize, Deserialize};
+
+pub const AUDIT_DEFAULT_LIMIT : i64 = 50;
+pub const AUDIT_MAX_LIMIT : i64 = 500;
+
+/// Administrative actions recorded in the audit log
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditAction {
+    #[serde(rename = "link:create")]
+    LinkCreate,
+    #[serde(rename = "link:update")]
+    LinkUpdate,
+    #[serde(rename = "link:delete")]
+    LinkDelete,
+    #[serde(rename = "key:create")]
+    KeyCreate,
+    #[serde(rename = "key:rotate")]
+    KeyRotate,
+    #[serde(rename = "key:delete")]
+    KeyDelete
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LinkCreate => "link:create",
+            AuditAction::LinkUpdate => "link:update",
+            AuditAction::LinkDelete => "link:delete",
+            AuditAction::KeyCreate => "key:create",
+            AuditAction::KeyRotate => "key:rotate",
+            AuditAction::KeyDelete => "key:delete"
+        }
+    }
+}
+
+/// Who made a request, used to fill in the audit log entries it causes
+pub struct AuditContext {
+    pub actor_key_id : Option<i64>,
+    pub ip : Option<String>
+}
+
+impl AuditContext {
+    /// For changes made outside of a request, such as keys created on startup or by `mint-key`
+    pub fn without_actor() -> Self {
+        Self { actor_key_id: None, ip: None }
+    }
+
+    /// Builds an entry for `action` on `target_id`. The old and new values are stored as JSON.
+    pub fn entry<T : Serialize>(&self, action : AuditAction, target_id : &str, old_value : Option<&T>, new_value : Option<&T>) -> AuditEntryDbInsert {
+        AuditEntryDbInsert {
+            actor_key_id: self.actor_key_id,
+            action: action.as_str().to_owned(),
+            target_id: target_id.to_owned(),
+            old_value: old_value.and_then(|val| serde_json::to_string(val).ok()),
+            new_value: new_value.and_then(|val| serde_json::to_string(val).ok()),
+            created_at: Utc::now().naive_utc(),
+            ip: self.ip.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditQuery {
+    /// Only entries caused by this API key id
+    pub actor : Option<i64>,
+    pub action : Option<AuditAction>,
+    /// Only entries about this link name or API key id
+    pub target : Option<String>,
+    pub since : Option<DateTime<Utc>>,
+    pub until : Option<DateTime<Utc>>,
+    pub limit : Option<i64>,
+    pub offset : Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id : i32,
+    pub actor_key_id : Option<i64>,
+    pub action : String,
+    pub target_id : String,
+    pub old_value : Option<serde_json::Value>,
+    pub new_value : Option<serde_json::Value>,
+    pub created_at : NaiveDateTime,
+    pub ip : Option<String>
+}
+
+impl From<AuditEntryDb> for AuditEntry {
+    fn from(u : AuditEntryDb) -> Self {
+        Self {
+            id: u.id,
+            actor_key_id: u.actor_key_id,
+            action: u.action,
+            target_id: u.target_id,
+            old_value: u.old_value.and_then(|val| serde_json::from_str(&val).ok()),
+            new_value: u.new_value.and_then(|val| serde_json::from_str(&val).ok()),
+            created_at: u.created_at,
+            ip: u.ip
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditListResponse {
+    pub entries : Vec<AuditEntry>,
+    pub total : i64,
+    pub limit : i64,
+    pub offset : i64
+}
+
+#[derive(Queryable)]
+pub struct AuditEntryDb {
+    pub id : i32,
+    pub actor_key_id : Option<i64>,
+    pub action : String,
+    pub target_id : String,
+    pub old_value : Option<String>,
+    pub new_value : Option<String>,
+    pub created_at : NaiveDateTime,
+    pub ip : Option<String>
+}
+
+#[derive(Insertable)]
+#[table_name="audit_log"]
+pub struct AuditEntryDbInsert {
+    pub actor_key_id : Option<i64>,
+    pub action : String,
+    pub target_id : String,
+    pub old_value : Option<String>,
+    pub new_value : Option<String>,
+    pub created_at : NaiveDateTime,
+    pub ip : Option<String>
+}
\ No newline at end of file
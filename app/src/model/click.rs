@@ -0,0 +1,25 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use crate::schema::{clicks};
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct ClickStats {
+    pub id : String,
+    pub total : i64,
+    pub per_day : Vec<ClickDayCount>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClickDayCount {
+    pub day : NaiveDate,
+    pub count : i64
+}
+
+#[derive(Insertable)]
+#[table_name="clicks"]
+pub struct ClickDbInsert {
+    pub url_id : String,
+    pub clicked_at : NaiveDateTime,
+    pub referrer : Option<String>,
+    pub user_agent : Option<String>
+}
@@ -1,4 +1,6 @@
 pub mod url;
 pub mod api_key;
+pub mod click;
+pub mod audit;
 pub mod db;
 pub mod error;
\ No newline at end of file
@@ -1,15 +1,157 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use crate::schema::{urls};
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
 
+/// HTTP status codes a link is allowed to redirect with
+pub const REDIRECT_TYPES : [u16; 5] = [301, 302, 303, 307, 308];
+
+pub fn is_valid_redirect_type(status : u16) -> bool {
+    REDIRECT_TYPES.contains(&status)
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Url {
     pub id : String,
-    pub url : String
+    pub url : String,
+    pub expires_at : Option<NaiveDateTime>,
+    pub redirect_type : Option<i32>,
+    pub updated_at : Option<NaiveDateTime>,
+    pub created_at : NaiveDateTime,
+    pub created_by : Option<i64>,
+    pub title : Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct UrlRequest {
     pub id : Option<String>,
     pub url : String,
+    pub expires_at : Option<DateTime<Utc>>,
+    pub ttl_seconds : Option<i64>,
+    pub redirect_type : Option<u16>,
+    /// Shown on the preview page of the link. The target page's own title is deliberately not fetched, so the server
+    /// never makes requests to URLs submitted by clients.
+    pub title : Option<String>,
+}
+
+impl UrlRequest {
+    /// Resolves the absolute expiry of the link, if any.
+    pub fn expiry(&self, now : NaiveDateTime) -> Result<Option<NaiveDateTime>, String> {
+        resolve_expiry(self.expires_at, self.ttl_seconds, now)
+    }
+}
+
+/// Partial update of an existing link. Fields left out are kept as-is, while an explicit `null` for
+/// `expires_at`, `redirect_type` or `title` clears the value.
+#[derive(Serialize, Deserialize)]
+pub struct UrlUpdateRequest {
+    pub url : Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub expires_at : Option<Option<DateTime<Utc>>>,
+    pub ttl_seconds : Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub redirect_type : Option<Option<u16>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub title : Option<Option<String>>,
+}
+
+impl UrlUpdateRequest {
+    /// Resolves the change to the link expiry. `None` leaves the expiry untouched, `Some(None)` clears it.
+    pub fn expiry(&self, now : NaiveDateTime) -> Result<Option<Option<NaiveDateTime>>, String> {
+        match (self.expires_at, self.ttl_seconds) {
+            (Some(None), Some(_)) => Err("expires_at can't be cleared while also setting ttl_seconds".to_owned()),
+            (Some(None), None) => Ok(Some(None)),
+            (expires_at, ttl) => resolve_expiry(expires_at.flatten(), ttl, now).map(|val| val.map(Some))
+        }
+    }
+}
+
+// Distinguishes an explicit `null` from a missing field when used together with `#[serde(default)]`
+fn deserialize_some<'de, T, D>(deserializer : D) -> Result<Option<T>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// Resolves an absolute expiry from either a timestamp or a TTL relative to `now`. The two are mutually exclusive.
+pub fn resolve_expiry(expires_at : Option<DateTime<Utc>>, ttl_seconds : Option<i64>, now : NaiveDateTime) -> Result<Option<NaiveDateTime>, String> {
+    match (expires_at, ttl_seconds) {
+        (Some(_), Some(_)) => Err("Only one of expires_at and ttl_seconds can be set".to_owned()),
+        (Some(expires_at), None) => {
+            let expires_at = expires_at.naive_utc();
+            if expires_at <= now {
+                return Err("expires_at must be in the future".to_owned());
+            }
+            Ok(Some(expires_at))
+        },
+        (None, Some(ttl)) => offset_seconds(now, ttl, "ttl_seconds").map(Some),
+        (None, None) => Ok(None)
+    }
+}
+
+/// Longest TTL or grace period accepted, about 100 years. Keeps the resulting timestamps well within chrono's range.
+pub const MAX_OFFSET_SECONDS : i64 = 100 * 365 * 24 * 60 * 60;
+
+/// Adds `seconds` to `now`, which must be greater than zero and at most `MAX_OFFSET_SECONDS`. `field` names the value
+/// in the error.
+pub fn offset_seconds(now : NaiveDateTime, seconds : i64, field : &str) -> Result<NaiveDateTime, String> {
+    if seconds <= 0 {
+        return Err(format!("{} must be greater than zero", field));
+    }
+    if seconds > MAX_OFFSET_SECONDS {
+        return Err(format!("{} can't be greater than {}", field, MAX_OFFSET_SECONDS));
+    }
+    now.checked_add_signed(Duration::seconds(seconds))
+        .ok_or_else(|| format!("{} is out of range", field))
+}
+
+/// Paths served by the API that a short id would otherwise shadow
+pub const RESERVED_IDS : [&str; 10] = ["new", "delete", "key", "audit", "links", "stats", "cache", "metrics", "healthz", "readyz"];
+
+/// Whether a custom short id can be claimed. Ids may not contain slashes or end with `+`, as those
+/// are used to address the preview page of a link, nor be one of `RESERVED_IDS`.
+pub fn is_valid_custom_id(id : &str) -> bool {
+    !id.is_empty() && !id.contains('/') && !id.ends_with('+') && !RESERVED_IDS.contains(&id)
+}
+
+/// Link record as returned by `GET /links/{id}`
+#[derive(Serialize, Deserialize)]
+pub struct UrlInfo {
+    #[serde(flatten)]
+    pub link : Url,
+    pub short_url : String,
+    pub clicks : i64
+}
+
+pub const QR_DEFAULT_SIZE : u32 = 256;
+pub const QR_MAX_SIZE : u32 = 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct QrQuery {
+    /// Either `png` or `svg`. Defaults to `png`
+    pub format : Option<String>,
+    /// Minimum width and height of the rendered code, in pixels
+    pub size : Option<u32>,
+}
+
+pub const LIST_DEFAULT_LIMIT : i64 = 50;
+pub const LIST_MAX_LIMIT : i64 = 500;
+
+#[derive(Serialize, Deserialize)]
+pub struct UrlListQuery {
+    /// Substring matched against both the short id and the destination URL
+    pub search : Option<String>,
+    pub limit : Option<i64>,
+    pub offset : Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UrlListResponse {
+    pub links : Vec<Url>,
+    pub total : i64,
+    pub limit : i64,
+    pub offset : i64
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,7 +163,13 @@ impl From<UrlDb> for Url {
     fn from(u: UrlDb) -> Self {
         Self {
             id: u.id,
-            url: u.url
+            url: u.url,
+            expires_at: u.expires_at,
+            redirect_type: u.redirect_type,
+            updated_at: u.updated_at,
+            created_at: u.created_at,
+            created_by: u.created_by,
+            title: u.title
         }
     }
 }
@@ -31,18 +179,51 @@ impl PartialEq for Url {
     }
 }
 
-#[derive(Queryable, Identifiable)]
+#[derive(Queryable, Identifiable, Clone)]
 #[table_name="urls"]
 pub struct UrlDb {
     pub id : String,
-    pub url : String
+    pub url : String,
+    pub expires_at : Option<NaiveDateTime>,
+    pub redirect_type : Option<i32>,
+    pub updated_at : Option<NaiveDateTime>,
+    pub created_at : NaiveDateTime,
+    pub created_by : Option<i64>,
+    pub title : Option<String>
+}
+
+impl UrlDb {
+    pub fn is_expired(&self, now : NaiveDateTime) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+
+    /// Title shown on the preview page, falling back to the host of the target URL rather than fetching its title
+    pub fn display_title(&self) -> String {
+        if let Some(title) = self.title.as_ref().filter(|val| !val.trim().is_empty()) {
+            return title.clone();
+        }
+        ::url::Url::parse(&self.url).ok()
+            .and_then(|val| val.host_str().map(|host| host.to_owned()))
+            .unwrap_or_else(|| self.url.clone())
+    }
+
+    /// Status code to redirect with, falling back to the server-wide default when the link has none set
+    pub fn redirect_status(&self, default : u16) -> u16 {
+        self.redirect_type.map_or(default, |val| val as u16)
+    }
 }
 
 impl From<Url> for UrlDb {
     fn from(u: Url) -> Self {
         Self {
             id: u.id,
-            url: u.url
+            url: u.url,
+            expires_at: u.expires_at,
+            redirect_type: u.redirect_type,
+            updated_at: u.updated_at,
+            created_at: u.created_at,
+            created_by: u.created_by,
+            title: u.title
         }
     }
 }
@@ -51,5 +232,50 @@ impl From<Url> for UrlDb {
 #[table_name="urls"]
 pub struct UrlDbInsert {
     pub id : String,
-    pub url : String
+    pub url : String,
+    pub expires_at : Option<NaiveDateTime>,
+    pub redirect_type : Option<i32>,
+    pub created_at : NaiveDateTime,
+    pub created_by : Option<i64>,
+    pub title : Option<String>
+}
+
+#[derive(AsChangeset)]
+#[table_name="urls"]
+pub struct UrlDbUpdate {
+    pub url : Option<String>,
+    pub expires_at : Option<Option<NaiveDateTime>>,
+    pub redirect_type : Option<Option<i32>>,
+    pub title : Option<Option<String>>,
+    pub updated_at : NaiveDateTime
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::from_timestamp(1_660_000_000, 0)
+    }
+
+    #[test]
+    fn ttl_is_added_to_now() {
+        assert_eq!(resolve_expiry(None, Some(60), now()), Ok(Some(now() + Duration::seconds(60))));
+        assert_eq!(resolve_expiry(None, Some(MAX_OFFSET_SECONDS), now()), Ok(Some(now() + Duration::seconds(MAX_OFFSET_SECONDS))));
+    }
+
+    #[test]
+    fn out_of_range_ttl_is_rejected() {
+        for ttl in [0, -1, MAX_OFFSET_SECONDS + 1, 9_300_000_000_000_000, i64::MAX, i64::MIN] {
+            assert!(resolve_expiry(None, Some(ttl), now()).is_err(), "ttl {} was accepted", ttl);
+        }
+    }
+
+    #[test]
+    fn huge_ttl_is_rejected_on_create_and_update() {
+        let create : UrlRequest = serde_json::from_str(r#"{"url": "https://example.com", "ttl_seconds": 9223372036854775807}"#).unwrap();
+        assert!(create.expiry(now()).is_err());
+        let update : UrlUpdateRequest = serde_json::from_str(r#"{"ttl_seconds": 9223372036854775807}"#).unwrap();
+        assert!(update.expiry(now()).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+use image::{ImageOutputFormat, Luma};
+use qrcode::QrCode;
+use qrcode::render::svg;
+
+pub fn render_png(data : &str, size : u32) -> anyhow::Result<Vec<u8>> {
+    let code = QrCode::new(data.as_bytes())?;
+    let image = code.render::<Luma<u8>>()
+        .min_dimensions(size, size)
+        .build();
+
+    let mut buf = Vec::new();
+    image::DynamicImage::ImageLuma8(image).write_to(&mut buf, ImageOutputFormat::Png)?;
+    Ok(buf)
+}
+
+pub fn render_svg(data : &str, size : u32) -> anyhow::Result<String> {
+    let code = QrCode::new(data.as_bytes())?;
+    Ok(code.render::<svg::Color>()
+        .min_dimensions(size, size)
+        .build())
+}
\ No newline at end of file
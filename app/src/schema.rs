@@ -1,8 +1,43 @@
 table! {
     api_keys (id) {
         id -> Integer,
-        key -> Text,
+        key_prefix -> Text,
+        key_hash -> Text,
+        key_salt -> Text,
+        legacy_key -> Nullable<Text>,
         description -> Nullable<Text>,
+        scopes -> Text,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        previous_key_prefix -> Nullable<Text>,
+        previous_key_hash -> Nullable<Text>,
+        previous_key_salt -> Nullable<Text>,
+        previous_key_expires_at -> Nullable<Timestamp>,
+        subject -> Nullable<Text>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Integer,
+        actor_key_id -> Nullable<BigInt>,
+        action -> Text,
+        target_id -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        created_at -> Timestamp,
+        ip -> Nullable<Text>,
+    }
+}
+
+table! {
+    clicks (id) {
+        id -> Integer,
+        url_id -> Text,
+        clicked_at -> Timestamp,
+        referrer -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
     }
 }
 
@@ -10,10 +45,18 @@ table! {
     urls (id) {
         id -> Text,
         url -> Text,
+        expires_at -> Nullable<Timestamp>,
+        redirect_type -> Nullable<Integer>,
+        updated_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        created_by -> Nullable<BigInt>,
+        title -> Nullable<Text>,
     }
 }
 
 allow_tables_to_appear_in_same_query!(
     api_keys,
+    audit_log,
+    clicks,
     urls,
 );
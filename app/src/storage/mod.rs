@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{NaiveDate, NaiveDateTime};
+use crate::model::api_key::{ApiKeyDb, ApiKeyDbInsert};
+use crate::model::audit::{AuditContext, AuditEntryDb, AuditQuery};
+use crate::model::click::ClickDbInsert;
+use crate::model::error::Error;
+use crate::model::url::{UrlDb, UrlDbInsert, UrlDbUpdate};
+
+#[macro_use]
+mod queries;
+mod postgres;
+mod sqlite;
+
+pub use postgres::PgStorage;
+pub use sqlite::SqliteStorage;
+
+pub type StorageResult<T> = Result<T, Error>;
+
+/// How long `check_ready` waits for a connection, so readiness probes fail rather than hang when the pool is exhausted
+pub const READY_CHECK_TIMEOUT : Duration = Duration::from_secs(5);
+
+/// Connections of the database pool, see `r2d2::State`
+pub struct PoolStatus {
+    pub connections : u32,
+    pub idle_connections : u32,
+    pub max_size : u32
+}
+
+/// Whether the caller may modify a given link
+pub enum LinkAccess {
+    Allowed,
+    NotFound,
+    Forbidden
+}
+
+/// Where links, clicks, API keys and the audit log are kept. Every method blocks on the database, so has to be run
+/// off the async workers, e.g. through `web::block`.
+pub trait Storage : Send + Sync {
+    /// Applies the migrations that haven't been run yet
+    fn run_migrations(&self) -> StorageResult<()>;
+    /// Takes a connection from the pool and checks that the migrations applied by `run_migrations` are still in
+    /// place, e.g. the database wasn't restored from an older backup. Returns false when they aren't.
+    fn check_ready(&self) -> StorageResult<bool>;
+    fn pool_status(&self) -> PoolStatus;
+
+    fn count_links(&self) -> StorageResult<i64>;
+    fn find_link(&self, link_id : &str) -> StorageResult<Option<UrlDb>>;
+    /// A link along with the amount of times it was clicked
+    fn find_link_with_clicks(&self, link_id : &str) -> StorageResult<Option<(UrlDb, i64)>>;
+    /// A page of links, newest first, and the total amount of links matching. `owner` limits the links to those
+    /// created by a key, `search` is matched literally against both the short id and the destination URL.
+    fn list_links(&self, owner : Option<i64>, search : Option<&str>, limit : i64, offset : i64) -> StorageResult<(i64, Vec<UrlDb>)>;
+    /// Returns false when the id of the link is already in use
+    fn create_link(&self, entry : &UrlDbInsert, audit : &AuditContext) -> StorageResult<bool>;
+    /// Applies `changes` to a link, provided it was created by `owner` when one is given
+    fn update_link(&self, link_id : &str, changes : &UrlDbUpdate, owner : Option<i64>, audit : &AuditContext) -> StorageResult<LinkAccess>;
+    /// Deletes a link and its clicks, provided it was created by `owner` when one is given
+    fn delete_link(&self, link_id : &str, owner : Option<i64>, audit : &AuditContext) -> StorageResult<LinkAccess>;
+    /// Deletes links whose `expires_at` has passed, along with their clicks. Returns the amount of links deleted.
+    fn delete_expired_links(&self, now : NaiveDateTime) -> StorageResult<usize>;
+
+    fn record_click(&self, entry : &ClickDbInsert) -> StorageResult<()>;
+    /// The amount of clicks on a link per day, oldest first and leaving out days without clicks, or `None` if the
+    /// link doesn't exist
+    fn click_counts(&self, link_id : &str) -> StorageResult<Option<Vec<(NaiveDate, i64)>>>;
+
+    fn list_keys(&self) -> StorageResult<Vec<ApiKeyDb>>;
+    fn count_keys(&self) -> StorageResult<i64>;
+    /// Keys whose current or rotated out secret starts with `prefix`
+    fn find_keys_by_prefix(&self, prefix : &str) -> StorageResult<Vec<ApiKeyDb>>;
+    /// Inserts a new key and returns the id it was given
+    fn create_key(&self, entry : &ApiKeyDbInsert, audit : &AuditContext) -> StorageResult<i64>;
+    /// Inserts a key only while there are no keys at all, returning `None` otherwise. When several instances start at
+    /// once, only one of them creates a key.
+    fn create_first_key(&self, entry : &ApiKeyDbInsert, audit : &AuditContext) -> StorageResult<Option<i64>>;
+    /// Swaps in `new_key` as the secret of a key, see `ApiKeyDbRotate`. Returns false if the key doesn't exist.
+    fn rotate_key(&self, key_id : i64, new_key : &str, grace_until : Option<NaiveDateTime>, audit : &AuditContext) -> StorageResult<bool>;
+    /// Returns false if the key doesn't exist
+    fn delete_key(&self, key_id : i64, audit : &AuditContext) -> StorageResult<bool>;
+    /// Returns the id of the key record of a JWT subject, creating it on first use. The scopes of such callers come
+    /// from their token, so the record itself has none.
+    fn subject_key_id(&self, subject : &str) -> StorageResult<i64>;
+    /// Stores when keys were last used, see `LastUsedTracker`
+    fn write_key_last_used(&self, used : &HashMap<i64, NaiveDateTime>) -> StorageResult<()>;
+    /// Hashes keys that are still stored in plaintext from before keys were hashed at rest. Returns the amount of
+    /// keys hashed.
+    fn hash_legacy_keys(&self) -> StorageResult<usize>;
+
+    /// A page of audit log entries matching `query`, newest first, and the total amount of entries matching
+    fn list_audit(&self, query : &AuditQuery, limit : i64, offset : i64) -> StorageResult<(i64, Vec<AuditEntryDb>)>;
+}
+
+/// Connects to the database at `database_url`. `postgres://` and `postgresql://` URLs use PostgreSQL, anything else
+/// is taken as the path of a SQLite database file.
+pub fn open(database_url : &str) -> anyhow::Result<Arc<dyn Storage>> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        Ok(Arc::new(PgStorage::new(database_url)?))
+    } else {
+        let path = database_url.strip_prefix("sqlite://").unwrap_or(database_url);
+        Ok(Arc::new(SqliteStorage::new(path)?))
+    }
+}
\ No newline at end of file
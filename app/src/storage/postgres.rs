@@ -0,0 +1,62 @@
+use std::sync::Mutex;
+use diesel::{Connection, PgConnection, QueryResult, RunQueryDsl};
+use diesel::r2d2::{self, ConnectionManager, PooledConnection};
+use crate::model::api_key::ApiKeyDbSubjectInsert;
+use crate::model::error::url_err_any;
+use crate::storage::StorageResult;
+
+embed_migrations!("../migrations_postgres");
+
+/// Key of the advisory lock taken by `locked_transaction`
+const WRITE_LOCK_KEY : i64 = 0x7572_6c00;
+/// Key of the advisory lock taken while running migrations
+const MIGRATION_LOCK_KEY : i64 = 0x7572_6c01;
+
+/// Storage in a PostgreSQL database, which can be shared by several instances of the service
+pub struct PgStorage {
+    pool : r2d2::Pool<ConnectionManager<PgConnection>>,
+    migrated_to : Mutex<Option<String>>
+}
+
+impl PgStorage {
+    pub fn new(database_url : &str) -> anyhow::Result<Self> {
+        let pool = r2d2::Pool::builder()
+            .build(ConnectionManager::<PgConnection>::new(database_url))?;
+        Ok(Self { pool, migrated_to: Mutex::new(None) })
+    }
+
+    fn conn(&self) -> StorageResult<PooledConnection<ConnectionManager<PgConnection>>> {
+        self.pool.get().map_err(url_err_any)
+    }
+
+    /// Holds an advisory lock while migrating, so instances starting at once don't apply the same migrations
+    /// concurrently
+    fn migrate(conn : &PgConnection) -> StorageResult<()> {
+        diesel::sql_query(format!("SELECT pg_advisory_lock({})", MIGRATION_LOCK_KEY)).execute(conn).map_err(url_err_any)?;
+        let result = embedded_migrations::run_with_output(conn, &mut std::io::stdout()).map_err(url_err_any);
+        diesel::sql_query(format!("SELECT pg_advisory_unlock({})", MIGRATION_LOCK_KEY)).execute(conn).map_err(url_err_any)?;
+        result
+    }
+
+    /// Runs `f` in a transaction holding an advisory lock, so other callers of `locked_transaction`, possibly on other
+    /// instances, wait until it's done
+    fn locked_transaction<T, F>(conn : &PgConnection, f : F) -> QueryResult<T>
+        where F : FnOnce() -> QueryResult<T>
+    {
+        conn.transaction(|| {
+            diesel::sql_query(format!("SELECT pg_advisory_xact_lock({})", WRITE_LOCK_KEY)).execute(conn)?;
+            f()
+        })
+    }
+
+    fn insert_subject(conn : &PgConnection, entry : &ApiKeyDbSubjectInsert) -> QueryResult<usize> {
+        use crate::model::api_key::db::api_keys::dsl::api_keys;
+
+        diesel::insert_into(api_keys)
+            .values(entry)
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+}
+
+diesel_storage!(PgStorage, PgConnection);
\ No newline at end of file
@@ -0,0 +1,490 @@
+// SQLite matches LIKE patterns case-insensitively, PostgreSQL doesn't, so searches lowercase both sides
+sql_function!(fn lower(x : diesel::sql_types::Text) -> diesel::sql_types::Text);
+// Both backends have a date() truncating timestamps to their day
+sql_function!(fn date(x : diesel::sql_types::Timestamp) -> diesel::sql_types::Date);
+
+/// Implements `Storage` for a Diesel backend. The queries are the same for every backend, but Diesel needs to know
+/// the concrete connection type to build them, so they are expanded once per backend. The backend has to provide
+/// `conn`, `migrate`, `locked_transaction` and `insert_subject` for what differs between databases, keep its r2d2
+/// pool in `pool`, and have a `migrated_to : Mutex<Option<String>>` for the latest migration applied at startup.
+macro_rules! diesel_storage {
+    ($storage:ident, $connection:ty) => {
+        impl $storage {
+            /// Checks that a link exists and, unless `owner` is `None`, that it was created by the given key
+            fn check_link_access(conn : &$connection, link_id : &str, owner : Option<i64>) -> diesel::QueryResult<$crate::storage::LinkAccess> {
+                use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+                use $crate::storage::LinkAccess;
+
+                let created_by : Option<Option<i64>> = $crate::schema::urls::table
+                    .filter($crate::schema::urls::id.eq(link_id))
+                    .select($crate::schema::urls::created_by)
+                    .first(conn)
+                    .optional()?;
+
+                Ok(match (created_by, owner) {
+                    (None, _) => LinkAccess::NotFound,
+                    (Some(_), None) => LinkAccess::Allowed,
+                    (Some(created_by), Some(owner)) if created_by == Some(owner) => LinkAccess::Allowed,
+                    _ => LinkAccess::Forbidden
+                })
+            }
+
+            /// Inserts a key and its audit log entry, returning the id the key was given
+            fn insert_key(conn : &$connection, entry : &$crate::model::api_key::ApiKeyDbInsert, audit : &$crate::model::audit::AuditContext) -> diesel::QueryResult<i64> {
+                use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::{ApiKey, ApiKeyDb};
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, key_hash};
+                use $crate::model::audit::AuditAction;
+
+                diesel::insert_into(api_keys)
+                    .values(entry)
+                    .execute(conn)?;
+                let created = api_keys
+                    .filter(key_hash.eq(&entry.key_hash))
+                    .first::<ApiKeyDb>(conn)?;
+                let new_id = created.id;
+                let created = ApiKey::from(created);
+                diesel::insert_into($crate::schema::audit_log::table)
+                    .values(&audit.entry(AuditAction::KeyCreate, &new_id.to_string(), None, Some(&created)))
+                    .execute(conn)?;
+                Ok(new_id)
+            }
+        }
+
+        impl $crate::storage::Storage for $storage {
+            fn run_migrations(&self) -> $crate::storage::StorageResult<()> {
+                use diesel_migrations::MigrationConnection;
+
+                let conn = self.conn()?;
+                Self::migrate(&conn)?;
+                *self.migrated_to.lock().unwrap() = conn.latest_run_migration_version()
+                    .map_err($crate::model::error::url_err_any)?;
+                Ok(())
+            }
+
+            fn check_ready(&self) -> $crate::storage::StorageResult<bool> {
+                use diesel_migrations::MigrationConnection;
+
+                let conn = self.pool.get_timeout($crate::storage::READY_CHECK_TIMEOUT)
+                    .map_err($crate::model::error::url_err_any)?;
+                let latest = conn.latest_run_migration_version()
+                    .map_err($crate::model::error::url_err_any)?;
+                // Versions are timestamps of the same length, so they sort as strings
+                Ok(latest >= *self.migrated_to.lock().unwrap())
+            }
+
+            fn pool_status(&self) -> $crate::storage::PoolStatus {
+                let state = self.pool.state();
+                $crate::storage::PoolStatus {
+                    connections: state.connections,
+                    idle_connections: state.idle_connections,
+                    max_size: self.pool.max_size()
+                }
+            }
+
+            fn count_links(&self) -> $crate::storage::StorageResult<i64> {
+                use diesel::{QueryDsl, RunQueryDsl};
+                use $crate::schema::urls::dsl::urls;
+
+                let conn = self.conn()?;
+                urls
+                    .count()
+                    .get_result(&conn)
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn find_link(&self, link_id : &str) -> $crate::storage::StorageResult<Option<$crate::model::url::UrlDb>> {
+                use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+                use $crate::schema::urls::dsl::{urls, id};
+
+                let conn = self.conn()?;
+                urls
+                    .filter(id.eq(link_id))
+                    .first::<$crate::model::url::UrlDb>(&conn)
+                    .optional()
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn find_link_with_clicks(&self, link_id : &str) -> $crate::storage::StorageResult<Option<($crate::model::url::UrlDb, i64)>> {
+                use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+                use $crate::schema::urls::dsl::{urls, id};
+                use $crate::schema::clicks::dsl::{clicks, url_id};
+
+                let conn = self.conn()?;
+                let link = urls
+                    .filter(id.eq(link_id))
+                    .first::<$crate::model::url::UrlDb>(&conn)
+                    .optional()
+                    .map_err($crate::model::error::url_err_any)?;
+                let link = match link {
+                    Some(val) => val,
+                    None => return Ok(None)
+                };
+                let click_count : i64 = clicks
+                    .filter(url_id.eq(link_id))
+                    .count()
+                    .get_result(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+                Ok(Some((link, click_count)))
+            }
+
+            fn list_links(&self, owner : Option<i64>, search : Option<&str>, limit : i64, offset : i64) -> $crate::storage::StorageResult<(i64, Vec<$crate::model::url::UrlDb>)> {
+                use diesel::{BoolExpressionMethods, EscapeExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, TextExpressionMethods};
+                use $crate::schema::urls::dsl::{urls, id, url, created_at, created_by};
+                use $crate::storage::queries::lower;
+
+                // Escape LIKE wildcards so the search term is matched literally
+                let pattern = search.map(|search| {
+                    format!("%{}%", search.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+                });
+                let filtered = || {
+                    let mut q = urls.into_boxed();
+                    if let Some(owner) = owner {
+                        q = q.filter(created_by.eq(owner));
+                    }
+                    if let Some(pattern) = &pattern {
+                        q = q.filter(lower(id).like(pattern.clone()).escape('\\').or(lower(url).like(pattern.clone()).escape('\\')));
+                    }
+                    q
+                };
+
+                let conn = self.conn()?;
+                let total = filtered()
+                    .count()
+                    .get_result(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+                let links = filtered()
+                    .order((created_at.desc(), id.asc()))
+                    .limit(limit)
+                    .offset(offset)
+                    .load::<$crate::model::url::UrlDb>(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+                Ok((total, links))
+            }
+
+            fn create_link(&self, entry : &$crate::model::url::UrlDbInsert, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<bool> {
+                use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+                use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+                use $crate::model::audit::AuditAction;
+                use $crate::model::url::{Url, UrlDb};
+                use $crate::schema::urls::dsl::{urls, id};
+
+                let conn = self.conn()?;
+                let db_resp = conn.transaction::<_, diesel::result::Error, _>(|| {
+                    diesel::insert_into(urls)
+                        .values(entry)
+                        .execute(&conn)?;
+                    let created = Url::from(urls.filter(id.eq(&entry.id)).first::<UrlDb>(&conn)?);
+                    diesel::insert_into($crate::schema::audit_log::table)
+                        .values(&audit.entry(AuditAction::LinkCreate, &created.id, None, Some(&created)))
+                        .execute(&conn)
+                });
+                match db_resp {
+                    Ok(_) => Ok(true),
+                    Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(false),
+                    Err(err) => Err($crate::model::error::url_err_any(err))
+                }
+            }
+
+            fn update_link(&self, link_id : &str, changes : &$crate::model::url::UrlDbUpdate, owner : Option<i64>, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<$crate::storage::LinkAccess> {
+                use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::audit::AuditAction;
+                use $crate::model::url::{Url, UrlDb};
+                use $crate::schema::urls::dsl::{urls, id};
+
+                let conn = self.conn()?;
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let access = Self::check_link_access(&conn, link_id, owner)?;
+                    if let $crate::storage::LinkAccess::Allowed = access {
+                        let before = Url::from(urls.filter(id.eq(link_id)).first::<UrlDb>(&conn)?);
+                        diesel::update(urls.filter(id.eq(link_id)))
+                            .set(changes)
+                            .execute(&conn)?;
+                        let after = Url::from(urls.filter(id.eq(link_id)).first::<UrlDb>(&conn)?);
+                        diesel::insert_into($crate::schema::audit_log::table)
+                            .values(&audit.entry(AuditAction::LinkUpdate, link_id, Some(&before), Some(&after)))
+                            .execute(&conn)?;
+                    }
+                    Ok(access)
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn delete_link(&self, link_id : &str, owner : Option<i64>, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<$crate::storage::LinkAccess> {
+                use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::audit::AuditAction;
+                use $crate::model::url::{Url, UrlDb};
+                use $crate::schema::urls::dsl::{urls, id};
+
+                let conn = self.conn()?;
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let access = Self::check_link_access(&conn, link_id, owner)?;
+                    if let $crate::storage::LinkAccess::Allowed = access {
+                        let deleted = Url::from(urls.filter(id.eq(link_id)).first::<UrlDb>(&conn)?);
+                        diesel::delete($crate::schema::clicks::table.filter($crate::schema::clicks::url_id.eq(link_id)))
+                            .execute(&conn)?;
+                        diesel::delete(urls.filter(id.eq(link_id)))
+                            .execute(&conn)?;
+                        diesel::insert_into($crate::schema::audit_log::table)
+                            .values(&audit.entry(AuditAction::LinkDelete, link_id, Some(&deleted), None))
+                            .execute(&conn)?;
+                    }
+                    Ok(access)
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn delete_expired_links(&self, now : chrono::NaiveDateTime) -> $crate::storage::StorageResult<usize> {
+                use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::schema::urls::dsl::{urls, id, expires_at};
+
+                let conn = self.conn()?;
+                conn.transaction(|| {
+                    let expired = urls
+                        .filter(expires_at.le(now))
+                        .select(id);
+                    diesel::delete($crate::schema::clicks::table.filter($crate::schema::clicks::url_id.eq_any(expired)))
+                        .execute(&conn)?;
+                    diesel::delete(urls.filter(expires_at.le(now)))
+                        .execute(&conn)
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn record_click(&self, entry : &$crate::model::click::ClickDbInsert) -> $crate::storage::StorageResult<()> {
+                use diesel::RunQueryDsl;
+
+                let conn = self.conn()?;
+                diesel::insert_into($crate::schema::clicks::table)
+                    .values(entry)
+                    .execute(&conn)
+                    .map(|_| ())
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn click_counts(&self, link_id : &str) -> $crate::storage::StorageResult<Option<Vec<(chrono::NaiveDate, i64)>>> {
+                use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+                use diesel::dsl::sql;
+                use diesel::query_dsl::GroupByDsl;
+                use $crate::schema::clicks::dsl::{clicks, clicked_at, url_id};
+                use $crate::storage::queries::date;
+
+                let conn = self.conn()?;
+                let link_count : i64 = $crate::schema::urls::table
+                    .filter($crate::schema::urls::id.eq(link_id))
+                    .count()
+                    .get_result(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+                if link_count == 0 {
+                    return Ok(None);
+                }
+                clicks
+                    .filter(url_id.eq(link_id))
+                    // Diesel doesn't allow mixing aggregates into the selection, so the count is written out
+                    .select((date(clicked_at), sql::<diesel::sql_types::BigInt>("COUNT(*)")))
+                    .order(date(clicked_at).asc())
+                    .group_by(date(clicked_at))
+                    .load::<(chrono::NaiveDate, i64)>(&conn)
+                    .map(Some)
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn list_keys(&self) -> $crate::storage::StorageResult<Vec<$crate::model::api_key::ApiKeyDb>> {
+                use diesel::RunQueryDsl;
+                use $crate::model::api_key::db::api_keys::dsl::api_keys;
+
+                let conn = self.conn()?;
+                api_keys
+                    .load::<$crate::model::api_key::ApiKeyDb>(&conn)
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn count_keys(&self) -> $crate::storage::StorageResult<i64> {
+                use diesel::{QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::db::api_keys::dsl::api_keys;
+
+                let conn = self.conn()?;
+                api_keys
+                    .count()
+                    .get_result(&conn)
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn find_keys_by_prefix(&self, prefix : &str) -> $crate::storage::StorageResult<Vec<$crate::model::api_key::ApiKeyDb>> {
+                use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, key_prefix, previous_key_prefix};
+
+                let conn = self.conn()?;
+                api_keys
+                    .filter(key_prefix.eq(prefix).or(previous_key_prefix.eq(prefix)))
+                    .load::<$crate::model::api_key::ApiKeyDb>(&conn)
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn create_key(&self, entry : &$crate::model::api_key::ApiKeyDbInsert, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<i64> {
+                use diesel::Connection;
+
+                let conn = self.conn()?;
+                conn.transaction(|| Self::insert_key(&conn, entry, audit))
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn create_first_key(&self, entry : &$crate::model::api_key::ApiKeyDbInsert, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<Option<i64>> {
+                use diesel::{QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::db::api_keys::dsl::api_keys;
+
+                let conn = self.conn()?;
+                Self::locked_transaction(&conn, || {
+                    let existing : i64 = api_keys.count().get_result(&conn)?;
+                    if existing > 0 {
+                        return Ok(None);
+                    }
+                    Self::insert_key(&conn, entry, audit).map(Some)
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn rotate_key(&self, key_id : i64, new_key : &str, grace_until : Option<chrono::NaiveDateTime>, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<bool> {
+                use diesel::{Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::{ApiKey, ApiKeyDb, ApiKeyDbRotate};
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, id};
+                use $crate::model::audit::AuditAction;
+
+                let conn = self.conn()?;
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let current = match api_keys.filter(id.eq(key_id)).first::<ApiKeyDb>(&conn).optional()? {
+                        Some(val) => val,
+                        None => return Ok(false)
+                    };
+                    diesel::update(api_keys.filter(id.eq(key_id)))
+                        .set(&ApiKeyDbRotate::new(new_key, &current, grace_until))
+                        .execute(&conn)?;
+                    let rotated = ApiKey::from(api_keys.filter(id.eq(key_id)).first::<ApiKeyDb>(&conn)?);
+                    diesel::insert_into($crate::schema::audit_log::table)
+                        .values(&audit.entry(AuditAction::KeyRotate, &key_id.to_string(), Some(&ApiKey::from(current)), Some(&rotated)))
+                        .execute(&conn)?;
+                    Ok(true)
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn delete_key(&self, key_id : i64, audit : &$crate::model::audit::AuditContext) -> $crate::storage::StorageResult<bool> {
+                use diesel::{Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::{ApiKey, ApiKeyDb};
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, id};
+                use $crate::model::audit::AuditAction;
+
+                let conn = self.conn()?;
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let deleted = match api_keys.filter(id.eq(key_id)).first::<ApiKeyDb>(&conn).optional()? {
+                        Some(val) => ApiKey::from(val),
+                        None => return Ok(false)
+                    };
+                    diesel::delete(api_keys.filter(id.eq(key_id)))
+                        .execute(&conn)?;
+                    diesel::insert_into($crate::schema::audit_log::table)
+                        .values(&audit.entry(AuditAction::KeyDelete, &key_id.to_string(), Some(&deleted), None))
+                        .execute(&conn)?;
+                    Ok(true)
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn subject_key_id(&self, jwt_subject : &str) -> $crate::storage::StorageResult<i64> {
+                use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::ApiKeyDbSubjectInsert;
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, id, subject};
+
+                let conn = self.conn()?;
+                Self::insert_subject(&conn, &ApiKeyDbSubjectInsert {
+                    key_prefix: String::new(),
+                    key_hash: String::new(),
+                    key_salt: String::new(),
+                    scopes: String::new(),
+                    created_at: chrono::Utc::now().naive_utc(),
+                    subject: jwt_subject.to_owned()
+                }).map_err($crate::model::error::url_err_any)?;
+                api_keys
+                    .filter(subject.eq(jwt_subject))
+                    .select(id)
+                    .first::<i64>(&conn)
+                    .map_err($crate::model::error::url_err_any)
+            }
+
+            fn write_key_last_used(&self, used : &std::collections::HashMap<i64, chrono::NaiveDateTime>) -> $crate::storage::StorageResult<()> {
+                use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, id, last_used_at};
+
+                let conn = self.conn()?;
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    for (key_id, used_at) in used {
+                        diesel::update(api_keys.filter(id.eq(key_id)))
+                            .set(last_used_at.eq(used_at))
+                            .execute(&conn)?;
+                    }
+                    Ok(())
+                }).map_err($crate::model::error::url_err_any)
+            }
+
+            fn hash_legacy_keys(&self) -> $crate::storage::StorageResult<usize> {
+                use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::model::api_key::{hash_key, new_salt, ApiKeyDb};
+                use $crate::model::api_key::db::api_keys::dsl::{api_keys, id, key_prefix, key_hash, key_salt, legacy_key};
+
+                let conn = self.conn()?;
+                let legacy : Vec<ApiKeyDb> = api_keys
+                    .filter(legacy_key.is_not_null())
+                    .load::<ApiKeyDb>(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+
+                for entry in &legacy {
+                    let plaintext = entry.legacy_key.as_ref().unwrap();
+                    let salt = new_salt();
+                    diesel::update(api_keys.filter(id.eq(entry.id)))
+                        .set((
+                            key_prefix.eq($crate::model::api_key::key_prefix(plaintext)),
+                            key_hash.eq(hash_key(plaintext, &salt)),
+                            key_salt.eq(&salt),
+                            legacy_key.eq(None::<String>)
+                        ))
+                        .execute(&conn)
+                        .map_err($crate::model::error::url_err_any)?;
+                }
+
+                Ok(legacy.len())
+            }
+
+            fn list_audit(&self, query : &$crate::model::audit::AuditQuery, limit : i64, offset : i64) -> $crate::storage::StorageResult<(i64, Vec<$crate::model::audit::AuditEntryDb>)> {
+                use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+                use $crate::schema::audit_log::dsl::{audit_log, id, actor_key_id, action, target_id, created_at};
+
+                let filtered = || {
+                    let mut q = audit_log.into_boxed();
+                    if let Some(actor) = query.actor {
+                        q = q.filter(actor_key_id.eq(actor));
+                    }
+                    if let Some(audit_action) = query.action {
+                        q = q.filter(action.eq(audit_action.as_str()));
+                    }
+                    if let Some(target) = &query.target {
+                        q = q.filter(target_id.eq(target.clone()));
+                    }
+                    if let Some(since) = query.since {
+                        q = q.filter(created_at.ge(since.naive_utc()));
+                    }
+                    if let Some(until) = query.until {
+                        q = q.filter(created_at.lt(until.naive_utc()));
+                    }
+                    q
+                };
+
+                let conn = self.conn()?;
+                let total = filtered()
+                    .count()
+                    .get_result(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+                let entries = filtered()
+                    .order((created_at.desc(), id.desc()))
+                    .limit(limit)
+                    .offset(offset)
+                    .load::<$crate::model::audit::AuditEntryDb>(&conn)
+                    .map_err($crate::model::error::url_err_any)?;
+                Ok((total, entries))
+            }
+        }
+    };
+}
\ No newline at end of file
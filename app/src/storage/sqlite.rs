@@ -0,0 +1,64 @@
+use std::sync::Mutex;
+use diesel::{QueryResult, RunQueryDsl, SqliteConnection};
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{self, ConnectionManager, PooledConnection};
+use crate::model::api_key::ApiKeyDbSubjectInsert;
+use crate::model::error::url_err_any;
+use crate::storage::StorageResult;
+
+embed_migrations!("../migrations");
+
+/// How long, in milliseconds, a connection waits for another one to finish writing before giving up
+const BUSY_TIMEOUT : u32 = 5000;
+
+/// Set up on every pooled connection. Write-ahead logging lets reads carry on while a click is recorded, and the busy
+/// timeout makes concurrent writes wait for each other instead of failing with "database is locked".
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl r2d2::CustomizeConnection<SqliteConnection, r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn : &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        conn.batch_execute(&format!("PRAGMA busy_timeout = {}; PRAGMA journal_mode = WAL;", BUSY_TIMEOUT))
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// Storage in a local SQLite database file. Only a single instance of the service can use it.
+pub struct SqliteStorage {
+    pool : r2d2::Pool<ConnectionManager<SqliteConnection>>,
+    migrated_to : Mutex<Option<String>>
+}
+
+impl SqliteStorage {
+    pub fn new(path : &str) -> anyhow::Result<Self> {
+        let pool = r2d2::Pool::builder()
+            .connection_customizer(Box::new(ConnectionOptions))
+            .build(ConnectionManager::<SqliteConnection>::new(path))?;
+        Ok(Self { pool, migrated_to: Mutex::new(None) })
+    }
+
+    fn conn(&self) -> StorageResult<PooledConnection<ConnectionManager<SqliteConnection>>> {
+        self.pool.get().map_err(url_err_any)
+    }
+
+    fn migrate(conn : &SqliteConnection) -> StorageResult<()> {
+        embedded_migrations::run_with_output(conn, &mut std::io::stdout()).map_err(url_err_any)
+    }
+
+    /// Runs `f` in a transaction holding the write lock from the start, so other writers wait until it's done
+    fn locked_transaction<T, F>(conn : &SqliteConnection, f : F) -> QueryResult<T>
+        where F : FnOnce() -> QueryResult<T>
+    {
+        conn.immediate_transaction(f)
+    }
+
+    fn insert_subject(conn : &SqliteConnection, entry : &ApiKeyDbSubjectInsert) -> QueryResult<usize> {
+        use crate::model::api_key::db::api_keys::dsl::api_keys;
+
+        diesel::insert_or_ignore_into(api_keys)
+            .values(entry)
+            .execute(conn)
+    }
+}
+
+diesel_storage!(SqliteStorage, SqliteConnection);
\ No newline at end of file
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::web;
+use chrono::Utc;
+use log::{info, warn};
+use crate::api::{JwtVerifier, LastUsedTracker};
+use crate::storage::Storage;
+use crate::tls::CertResolver;
+
+/// Periodically deletes links whose `expires_at` has passed, along with their recorded clicks.
+pub async fn sweep_expired_urls(storage : web::Data<dyn Storage>, interval_secs : u64) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+
+        let moved_storage = storage.clone();
+        let db_resp = web::block(move || moved_storage.delete_expired_links(Utc::now().naive_utc())).await;
+
+        match db_resp {
+            Ok(Ok(0)) => {},
+            Ok(Ok(count)) => info!("Swept {} expired link(s)", count),
+            Ok(Err(err)) => warn!("Unable to sweep expired links: {}", err.err_msg()),
+            Err(err) => warn!("Unable to sweep expired links: {}", err)
+        }
+    }
+}
+
+/// Periodically writes the API key usage collected by `LastUsedTracker` to the database
+pub async fn flush_key_last_used(storage : web::Data<dyn Storage>, tracker : web::Data<LastUsedTracker>, interval_secs : u64) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+
+        let moved_storage = storage.clone();
+        let moved_tracker = tracker.clone();
+        let db_resp = web::block(move || write_key_last_used(moved_storage.get_ref(), &moved_tracker)).await;
+
+        match db_resp {
+            Ok(Err(err)) => warn!("Unable to store API key usage: {}", err.err_msg()),
+            Err(err) => warn!("Unable to store API key usage: {}", err),
+            _ => {}
+        }
+    }
+}
+
+/// How often, in seconds, fetching the JWKS is retried while no keys could be loaded yet
+const JWKS_RETRY_INTERVAL : u64 = 30;
+
+/// Periodically fetches the JWKS again, so signing keys rotated by the OIDC provider are picked up. While no keys are
+/// loaded, e.g. because the provider couldn't be reached at startup, it's retried every `JWKS_RETRY_INTERVAL`.
+pub async fn refresh_jwks(verifier : web::Data<JwtVerifier>, interval_secs : u64) {
+    loop {
+        let wait = if verifier.has_keys() { interval_secs } else { interval_secs.min(JWKS_RETRY_INTERVAL) };
+        actix_web::rt::time::sleep(Duration::from_secs(wait.max(1))).await;
+
+        if let Err(err) = verifier.refresh().await {
+            warn!("Unable to refresh JWKS: {}", err);
+        }
+    }
+}
+
+/// Periodically checks the TLS certificate files, so renewed certificates are picked up
+pub async fn reload_tls_certs(resolver : Arc<CertResolver>, interval_secs : u64) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs.max(1)));
+    // The first tick completes immediately, and the certificates were just loaded
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+
+        let moved_resolver = resolver.clone();
+        match web::block(move || moved_resolver.reload()).await {
+            Ok(Err(err)) => warn!("Unable to reload TLS certificates: {:#}", err),
+            Err(err) => warn!("Unable to reload TLS certificates: {}", err),
+            _ => {}
+        }
+    }
+}
+
+/// Writes the API key usage collected so far. Blocking, so has to be run off the async workers.
+pub fn write_key_last_used(storage : &dyn Storage, tracker : &LastUsedTracker) -> Result<(), crate::model::error::Error> {
+    let pending = tracker.drain();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    storage.write_key_last_used(&pending)
+}
\ No newline at end of file
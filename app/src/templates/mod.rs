@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+/// Preview page shown for `/{id}+` and `/{id}/preview`
+pub static PREVIEW : Template = Template::new(include_str!("preview.html"));
+
+/// A HTML template with `{{name}}` placeholders. Values are HTML escaped when rendered, and placeholders
+/// without a value are rendered as empty strings.
+pub struct Template {
+    source : &'static str
+}
+
+impl Template {
+    pub const fn new(source : &'static str) -> Self {
+        Self { source }
+    }
+
+    pub fn render(&self, vars : &HashMap<&str, String>) -> String {
+        let mut output = String::with_capacity(self.source.len());
+        let mut rest = self.source;
+
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after_open = &rest[start + 2..];
+            match after_open.find("}}") {
+                Some(end) => {
+                    let name = after_open[..end].trim();
+                    if let Some(val) = vars.get(name) {
+                        output.push_str(&escape_html(val));
+                    }
+                    rest = &after_open[end + 2..];
+                },
+                None => {
+                    // Unterminated placeholder, keep the remainder as-is
+                    output.push_str(&rest[start..]);
+                    rest = "";
+                }
+            }
+        }
+        output.push_str(rest);
+
+        output
+    }
+}
+
+pub fn escape_html(input : &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c)
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(escape_html(r#"<a href="x" title='y'>&amp;</a>"#), "&lt;a href=&quot;x&quot; title=&#39;y&#39;&gt;&amp;amp;&lt;/a&gt;");
+        assert_eq!(escape_html("plain text"), "plain text");
+    }
+
+    #[test]
+    fn preview_escapes_title_and_target() {
+        let title = r#"<script>alert("x")</script> Tom & Jerry's"#;
+        let target = r#"https://example.com/?a=1&b="><script>alert('x')</script>"#;
+        let mut vars = HashMap::new();
+        vars.insert("title", title.to_owned());
+        vars.insert("short_url", "https://s.test/abc".to_owned());
+        vars.insert("target", target.to_owned());
+
+        let html = PREVIEW.render(&vars);
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains(title));
+        assert!(!html.contains(target));
+        assert!(html.contains("<title>&lt;script&gt;alert(&quot;x&quot;)&lt;/script&gt; Tom &amp; Jerry&#39;s</title>"));
+        assert!(html.contains("https://example.com/?a=1&amp;b=&quot;&gt;&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+    }
+
+    #[test]
+    fn missing_values_render_empty() {
+        let html = Template::new("<p>{{ missing }}</p>{{unterminated").render(&HashMap::new());
+        assert_eq!(html, "<p></p>{{unterminated");
+    }
+}
\ No newline at end of file
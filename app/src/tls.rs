@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use anyhow::{anyhow, Context};
+use log::info;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use crate::config::Config;
+
+/// A certificate and its key, along with when their files were last modified
+struct LoadedCert {
+    key : Arc<CertifiedKey>,
+    modified : (SystemTime, SystemTime)
+}
+
+struct CertEntry {
+    /// Lowercase server names this certificate is served for. Empty for the default certificate.
+    server_names : Vec<String>,
+    cert_path : String,
+    key_path : String,
+    loaded : RwLock<LoadedCert>
+}
+
+/// Picks the certificate for a TLS handshake by the server name the client asks for (SNI), falling back to
+/// `tls_cert`. `reload` reads certificates again once their files change, so renewed certificates are served without
+/// restarting the server.
+pub struct CertResolver {
+    /// The default certificate comes first
+    entries : Vec<CertEntry>
+}
+
+impl CertResolver {
+    pub fn load(conf : &Config) -> anyhow::Result<Self> {
+        let (cert_path, key_path) = match (&conf.tls_cert, &conf.tls_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => return Err(anyhow!("tls_cert and tls_key are required for TLS"))
+        };
+
+        let mut entries = vec![CertEntry::load(Vec::new(), cert_path, key_path)?];
+        for sni in &conf.tls_sni {
+            let server_names = sni.server_names.iter().map(|name| name.to_lowercase()).collect();
+            entries.push(CertEntry::load(server_names, &sni.cert, &sni.key)?);
+        }
+        Ok(Self { entries })
+    }
+
+    /// Reads the certificates whose files changed since they were loaded. A certificate that can't be read is kept
+    /// as is, so a renewal that's only half written doesn't take the server down.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        // Keep going after a failure, so one broken certificate doesn't hold up the others
+        let mut result = Ok(());
+        for entry in &self.entries {
+            if let Err(err) = entry.reload() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    pub fn server_config(self : &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello : ClientHello) -> Option<Arc<CertifiedKey>> {
+        let entry = client_hello.server_name()
+            .map(|name| name.to_lowercase())
+            .and_then(|name| self.entries.iter().find(|entry| entry.server_names.contains(&name)))
+            .unwrap_or(&self.entries[0]);
+        Some(entry.loaded.read().unwrap().key.clone())
+    }
+}
+
+impl CertEntry {
+    fn load(server_names : Vec<String>, cert_path : &str, key_path : &str) -> anyhow::Result<Self> {
+        let modified = modified_times(cert_path, key_path)?;
+        let key = load_certified_key(cert_path, key_path)?;
+        Ok(Self {
+            server_names,
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            loaded: RwLock::new(LoadedCert { key, modified })
+        })
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let modified = modified_times(&self.cert_path, &self.key_path)?;
+        if self.loaded.read().unwrap().modified == modified {
+            return Ok(());
+        }
+
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.loaded.write().unwrap() = LoadedCert { key, modified };
+        info!("Reloaded TLS certificate {}", self.cert_path);
+        Ok(())
+    }
+}
+
+fn modified_times(cert_path : &str, key_path : &str) -> anyhow::Result<(SystemTime, SystemTime)> {
+    let modified = |path : &str| std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("Unable to read {}", path));
+    Ok((modified(cert_path)?, modified(key_path)?))
+}
+
+fn load_certified_key(cert_path : &str, key_path : &str) -> anyhow::Result<Arc<CertifiedKey>> {
+    let mut cert_reader = BufReader::new(File::open(cert_path).with_context(|| format!("Unable to open {}", cert_path))?);
+    let certs : Vec<Certificate> = rustls_pemfile::certs(&mut cert_reader)
+        .with_context(|| format!("Unable to read certificates from {}", cert_path))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", cert_path));
+    }
+
+    let mut key_reader = BufReader::new(File::open(key_path).with_context(|| format!("Unable to open {}", key_path))?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader).with_context(|| format!("Unable to read private key from {}", key_path))? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(anyhow!("No private key found in {}", key_path))
+        }
+    };
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|_| anyhow!("Unsupported private key type in {}", key_path))?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
\ No newline at end of file
@@ -1,70 +1,459 @@
+use std::collections::HashMap;
 use std::str::FromStr;
-use actix_web::{middleware, web, App, HttpRequest, HttpServer, Error, HttpResponse};
-use diesel::{RunQueryDsl, QueryDsl, ExpressionMethods, SqliteConnection};
-use diesel::r2d2::{self, ConnectionManager};
-use diesel::result::DatabaseErrorKind;
-use crate::model::url::{UrlDb, UrlDbInsert, UrlDeleteRequest, UrlRequest};
-use crate::schema;
-use log::info;
-use thiserror::private::DisplayAsDisplay;
-use crate::api::{DefaultHeaders, AuthMiddleware};
-use crate::model::api_key::{ApiKey, ApiKeyDb, ApiKeyDbInsert, ApiKeyDeleteRequest, ApiKeyPostRequest, ApiKeyPostResponse};
-use crate::model::db::{DATABASE_URL, get_db_path};
-use crate::model::error::{url_err_any, url_err_request};
-use crate::model::error::Error::RequestError;
-
-pub type DbPool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{middleware, web, App, HttpMessage, HttpRequest, HttpServer, Error, HttpResponse};
+use actix_web::http::{KeepAlive, Method, StatusCode};
+use chrono::{NaiveDate, Utc};
+use crate::model::url::{is_valid_custom_id, is_valid_redirect_type, offset_seconds, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT, QR_DEFAULT_SIZE, QR_MAX_SIZE, QrQuery, REDIRECT_TYPES, RESERVED_IDS, Url, UrlDb, UrlDbInsert, UrlDbUpdate, UrlDeleteRequest, UrlInfo, UrlListQuery, UrlListResponse, UrlRequest, UrlUpdateRequest};
+use crate::qr;
+use crate::templates;
+use log::{info, warn};
+use crate::config::Listener;
+use crate::tls::CertResolver;
+use crate::api::{client_ip, DefaultHeaders, ForwardedIp, AuthMiddleware, JwtVerifier, KeyCache, LastUsedTracker, LinkCache, Metrics, Principal, RateLimit, RateLimiter, RequestMetrics};
+use crate::model::click::{ClickDayCount, ClickDbInsert, ClickStats};
+use crate::model::audit::{AUDIT_DEFAULT_LIMIT, AUDIT_MAX_LIMIT, AuditContext, AuditEntry, AuditListResponse, AuditQuery};
+use crate::model::api_key::{generate_key, Scope, ApiKey, ApiKeyDbInsert, ApiKeyDeleteRequest, ApiKeyPostRequest, ApiKeyPostResponse, ApiKeyRotateRequest};
+use crate::model::error::url_err_request;
+use crate::storage::{LinkAccess, Storage, StorageResult};
 
 #[actix_web::main]
-pub async fn start_server() {
-    let manager = ConnectionManager::<SqliteConnection>::new(format!("{}/{}", get_db_path(), DATABASE_URL));
-    let pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
+pub async fn start_server(storage : Arc<dyn Storage>, conf : crate::config::Config) {
+    let storage : web::Data<dyn Storage> = web::Data::from(storage);
+    let last_used = web::Data::new(LastUsedTracker::default());
+    let key_cache = web::Data::new(KeyCache::new(conf.key_cache_ttl));
+    let link_cache = web::Data::new(LinkCache::new(conf.link_cache_size, conf.link_cache_ttl, conf.link_cache_negative_ttl));
+    let metrics = web::Data::new(Metrics::new(&link_cache).expect("Unable to register metrics"));
+    actix_web::rt::spawn(crate::tasks::sweep_expired_urls(storage.clone(), conf.expired_sweep_interval));
+    actix_web::rt::spawn(crate::tasks::flush_key_last_used(storage.clone(), last_used.clone(), conf.key_last_used_flush_interval));
+
+    let jwt_verifier = if conf.auth_mode.accepts_jwts() {
+        let verifier = web::Data::new(JwtVerifier::load(&conf).await.expect("Unable to load JWKS"));
+        if verifier.is_remote() {
+            actix_web::rt::spawn(crate::tasks::refresh_jwks(verifier.clone(), conf.jwt_jwks_refresh_interval));
+        }
+        Some(verifier)
+    } else {
+        None
+    };
 
+    let api_limiter = Arc::new(RateLimiter::new(conf.rate_limit_api_burst, conf.rate_limit_api_per_minute, ForwardedIp::from_conf(&conf)));
+    let redirect_limiter = Arc::new(RateLimiter::new(conf.rate_limit_redirect_burst, conf.rate_limit_redirect_per_minute, ForwardedIp::from_conf(&conf)));
+    let qr_limiter = Arc::new(RateLimiter::new(conf.rate_limit_qr_burst, conf.rate_limit_qr_per_minute, ForwardedIp::from_conf(&conf)));
 
-    HttpServer::new(move || {
+    let listeners = conf.listeners().expect("Invalid listen address");
+    let tls_listeners = conf.tls_listeners().expect("Invalid TLS listen address");
+    let tls_config = if conf.tls_enabled() {
+        let resolver = Arc::new(CertResolver::load(&conf).expect("Unable to load TLS certificates"));
+        actix_web::rt::spawn(crate::tasks::reload_tls_certs(resolver.clone(), conf.tls_reload_interval));
+        Some(resolver.server_config())
+    } else {
+        None
+    };
+    let max_payload = conf.max_payload;
+    let server_storage = storage.clone();
+    let server_last_used = last_used.clone();
+    let mut server = HttpServer::new(move || {
         let app_conf = crate::config::load_conf().unwrap();
+        let storage = server_storage.clone();
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(storage.clone())
             .app_data(web::Data::new(app_conf))
+            .app_data(server_last_used.clone())
+            .app_data(key_cache.clone())
+            .app_data(link_cache.clone())
+            .app_data(metrics.clone())
+            .app_data(web::PayloadConfig::new(max_payload))
+            .configure(|cfg| {
+                if let Some(verifier) = &jwt_verifier {
+                    cfg.app_data(verifier.clone());
+                }
+            })
             // enable logger
             .wrap(middleware::Logger::default())
             .wrap(DefaultHeaders)
-            .service(web::resource("/new").to(new_url_handler).wrap(AuthMiddleware {pool: pool.clone()}))
-            .service(web::resource("/delete").to(delete_url_handler).wrap(AuthMiddleware {pool: pool.clone()}))
-            .service(web::resource("/key").to(key_handler).wrap(AuthMiddleware {pool: pool.clone()}))
-            .service(web::resource("/{id}").to(url_handler))
+            .wrap(RequestMetrics::new(metrics.clone()))
+            // The rate limits wrap inside AuthMiddleware, as they need to know which key is making the request
+            .service(web::resource("/new").to(new_url_handler)
+                .wrap(RateLimit::per_api_key(api_limiter.clone()))
+                .wrap(AuthMiddleware::new(storage.clone()).require(Scope::LinksWrite)))
+            .service(web::resource("/delete").to(delete_url_handler)
+                .wrap(RateLimit::per_api_key(api_limiter.clone()))
+                .wrap(AuthMiddleware::new(storage.clone()).require(Scope::LinksDelete)))
+            .service(web::resource("/key").to(key_handler)
+                .wrap(RateLimit::per_api_key(api_limiter.clone()))
+                .wrap(AuthMiddleware::new(storage.clone()).require(Scope::KeysAdmin)))
+            .service(web::resource("/key/{id}/rotate").to(key_rotate_handler)
+                .wrap(RateLimit::per_api_key(api_limiter.clone()))
+                .wrap(AuthMiddleware::new(storage.clone()).require(Scope::KeysAdmin)))
+            .service(web::resource("/audit").to(audit_handler).wrap(AuthMiddleware::new(storage.clone()).require(Scope::KeysAdmin)))
+            .service(web::resource("/links").to(links_handler).wrap(AuthMiddleware::new(storage.clone()).require(Scope::LinksRead)))
+            .service(web::resource("/links/{id}").to(link_handler).wrap(AuthMiddleware::new(storage.clone())
+                .require_for(Method::GET, Scope::LinksRead)
+                .require_for(Method::PATCH, Scope::LinksWrite)))
+            .service(web::resource("/stats/{id}").to(stats_handler).wrap(AuthMiddleware::new(storage.clone()).require(Scope::StatsRead)))
+            .service(web::resource("/cache/stats").to(cache_stats_handler).wrap(AuthMiddleware::new(storage.clone()).require(Scope::StatsRead)))
+            .service(web::resource("/metrics").to(metrics_handler))
+            .service(web::resource("/healthz").to(healthz_handler))
+            .service(web::resource("/readyz").to(readyz_handler))
+            .service(web::resource("/{id}/preview").to(preview_handler).wrap(RateLimit::per_ip(redirect_limiter.clone())))
+            .service(web::resource("/{id}/qr").to(qr_handler).wrap(RateLimit::per_ip(qr_limiter.clone())))
+            .service(web::resource("/{id}").to(url_handler).wrap(RateLimit::per_ip(redirect_limiter.clone())))
     })
-        .bind(("0.0.0.0", 8380)).unwrap()
-        .run()
-        .await.unwrap();
+        .keep_alive(match conf.keep_alive {
+            0 => KeepAlive::Disabled,
+            secs => KeepAlive::Timeout(Duration::from_secs(secs))
+        })
+        // The backlog only applies to listeners bound after it is set
+        .backlog(conf.backlog);
+    if let Some(workers) = conf.workers {
+        server = server.workers(workers);
+    }
+    for listener in &listeners {
+        server = match listener {
+            Listener::Tcp(addr) => server.bind(addr),
+            #[cfg(unix)]
+            Listener::Unix(path) => server.bind_uds(path),
+            #[cfg(not(unix))]
+            Listener::Unix(_) => panic!("Unix domain sockets aren't supported on this platform")
+        }.unwrap_or_else(|err| panic!("Unable to listen on {}: {}", listener, err));
+        info!("Listening on {}", listener);
+    }
+    if let Some(tls_config) = &tls_config {
+        for addr in &tls_listeners {
+            server = server.bind_rustls(addr, tls_config.clone())
+                .unwrap_or_else(|err| panic!("Unable to listen on {}: {}", addr, err));
+            info!("Listening on {} (TLS)", addr);
+        }
+    }
+
+    server.run().await.unwrap();
+
+    // Don't lose the API key usage collected since the last periodic flush
+    if let Err(err) = web::block(move || crate::tasks::write_key_last_used(storage.get_ref(), &last_used)).await {
+        warn!("Unable to store API key usage: {}", err);
+    }
 }
 
-async fn url_handler(req: HttpRequest, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn url_handler(req: HttpRequest, storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, metrics : web::Data<Metrics>, conf : web::Data<crate::config::Config>) -> Result<HttpResponse, Error> {
     info!("url_handler triggered");
     if req.method().as_str() != "GET" {
         return Ok(HttpResponse::MethodNotAllowed().finish())
     }
     let path = req.path().strip_prefix('/').unwrap().to_string();
+    if let Some(link_id) = path.strip_suffix('+') {
+        return preview(storage, link_cache, conf, link_id.to_owned()).await;
+    }
 
-    let urls : Vec<UrlDb> = web::block(move || {
-        let conn = pool.get().map_err(url_err_any)?;
-        schema::urls::dsl::urls
-            .filter(schema::urls::id.eq(path))
-            .limit(1)
-            .load::<UrlDb>(&conn)
-            .map_err(url_err_any)
-    })
+    let resp = redirect(&req, storage, &link_cache, &conf, path).await?;
+    metrics.record_redirect(resp.status());
+    Ok(resp)
+}
+
+async fn redirect(req: &HttpRequest, storage: web::Data<dyn Storage>, link_cache : &LinkCache, conf : &crate::config::Config, path : String) -> Result<HttpResponse, Error> {
+    let url_entry = match find_url(storage.clone(), link_cache, path.clone()).await? {
+        Some(val) => val,
+        None => return Ok(HttpResponse::NotFound().finish())
+    };
+    if url_entry.is_expired(Utc::now().naive_utc()) {
+        return Ok(HttpResponse::Gone().finish());
+    }
+
+    record_click(req, storage, path);
+    let status = StatusCode::from_u16(url_entry.redirect_status(conf.default_redirect_type)).unwrap();
+    Ok(HttpResponse::build(status).insert_header(("Location", url_entry.url.as_str())).finish())
+}
+
+async fn preview_handler(req: HttpRequest, storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, conf : web::Data<crate::config::Config>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish())
+    }
+    let link_id = req.match_info().get("id").unwrap().to_string();
+
+    preview(storage, link_cache, conf, link_id).await
+}
+
+/// Renders the interstitial page showing where a link leads, instead of redirecting
+async fn preview(storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, conf : web::Data<crate::config::Config>, link_id : String) -> Result<HttpResponse, Error> {
+    let url_entry = match find_url(storage, &link_cache, link_id).await? {
+        Some(val) => val,
+        None => return Ok(HttpResponse::NotFound().finish())
+    };
+    if url_entry.is_expired(Utc::now().naive_utc()) {
+        return Ok(HttpResponse::Gone().finish());
+    }
+
+    let mut vars = HashMap::new();
+    vars.insert("title", url_entry.display_title());
+    vars.insert("short_url", format!("{}/{}", &conf.hostname, url_entry.id));
+    vars.insert("target", url_entry.url.clone());
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(templates::PREVIEW.render(&vars)))
+}
+
+async fn qr_handler(req: HttpRequest, storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, conf : web::Data<crate::config::Config>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish())
+    }
+    let link_id = req.match_info().get("id").unwrap().to_string();
+    let query = web::Query::<QrQuery>::from_query(req.query_string()).map_err(actix_web::error::ErrorBadRequest)?.into_inner();
+    let size = query.size.unwrap_or(QR_DEFAULT_SIZE).clamp(1, QR_MAX_SIZE);
+
+    let url_entry = match find_url(storage, &link_cache, link_id).await? {
+        Some(val) => val,
+        None => return Ok(HttpResponse::NotFound().finish())
+    };
+    if url_entry.is_expired(Utc::now().naive_utc()) {
+        return Ok(HttpResponse::Gone().finish());
+    }
+    let short_url = format!("{}/{}", &conf.hostname, url_entry.id);
+
+    match query.format.as_deref().unwrap_or("png") {
+        "png" => {
+            let png = web::block(move || qr::render_png(&short_url, size))
+                .await?
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok(HttpResponse::Ok().content_type("image/png").body(png))
+        },
+        "svg" => {
+            let svg = qr::render_svg(&short_url, size).map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg))
+        },
+        _ => Err("format must be either png or svg".to_owned()).map_err(actix_web::error::ErrorBadRequest)
+    }
+}
+
+/// Looks up a link through the redirect cache, only going to the database when it isn't cached
+async fn find_url(storage: web::Data<dyn Storage>, link_cache : &LinkCache, link_id : String) -> Result<Option<UrlDb>, Error> {
+    if let Some(cached) = link_cache.get(&link_id) {
+        return Ok(cached);
+    }
+
+    let lookup_id = link_id.clone();
+    let found = web::block(move || storage.find_link(&lookup_id))
         .await?
         .map_err(actix_web::error::ErrorInternalServerError)?;
+    link_cache.insert(&link_id, found.clone());
+    Ok(found)
+}
 
-    if urls.len() > 0 {
-        let url_entry = urls.first().unwrap();
-        Ok(HttpResponse::TemporaryRedirect().insert_header(("Location", url_entry.url.as_str())).finish())
-    } else {
-        Ok(HttpResponse::NotFound().finish())
+async fn cache_stats_handler(req: HttpRequest, link_cache : web::Data<LinkCache>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+
+    Ok(HttpResponse::Ok().json(&link_cache.stats()))
+}
+
+async fn metrics_handler(req: HttpRequest, storage: web::Data<dyn Storage>, metrics : web::Data<Metrics>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+
+    let pool = storage.pool_status();
+    let totals = match web::block(move || -> StorageResult<(i64, i64)> {
+        Ok((storage.count_links()?, storage.count_keys()?))
+    }).await? {
+        Ok(totals) => Some(totals),
+        Err(err) => {
+            warn!("Unable to count links and keys for metrics: {}", err);
+            None
+        }
+    };
+
+    let body = metrics.render(pool, totals).map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
+
+/// Liveness probe, answers as long as the server is handling requests
+async fn healthz_handler(req: HttpRequest) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+
+    Ok(HttpResponse::Ok().body("ok"))
+}
+
+/// Readiness probe, answers 503 while the database can't be queried or is missing migrations
+async fn readyz_handler(req: HttpRequest, storage: web::Data<dyn Storage>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+
+    match web::block(move || storage.check_ready()).await? {
+        Ok(true) => Ok(HttpResponse::Ok().body("ok")),
+        Ok(false) => {
+            warn!("Readiness check failed: database is missing migrations");
+            Ok(HttpResponse::ServiceUnavailable().body("database is missing migrations"))
+        },
+        Err(err) => {
+            warn!("Readiness check failed: {}", err.err_msg());
+            Ok(HttpResponse::ServiceUnavailable().body("database unavailable"))
+        }
+    }
+}
+
+async fn links_handler(req: HttpRequest, storage: web::Data<dyn Storage>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
     }
+    let query = web::Query::<UrlListQuery>::from_query(req.query_string()).map_err(actix_web::error::ErrorBadRequest)?.into_inner();
+    let limit = query.limit.unwrap_or(LIST_DEFAULT_LIMIT).clamp(1, LIST_MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let owner = link_owner_filter(&req);
+    let (total, links) = web::block(move || storage.list_links(owner, query.search.as_deref(), limit, offset))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let resp = UrlListResponse {
+        links: links.into_iter().map(Url::from).collect(),
+        total,
+        limit,
+        offset
+    };
+
+    Ok(HttpResponse::Ok().json(&resp))
+}
+
+async fn audit_handler(req: HttpRequest, storage: web::Data<dyn Storage>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+    let query = web::Query::<AuditQuery>::from_query(req.query_string()).map_err(actix_web::error::ErrorBadRequest)?.into_inner();
+    let limit = query.limit.unwrap_or(AUDIT_DEFAULT_LIMIT).clamp(1, AUDIT_MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let (total, entries) = web::block(move || storage.list_audit(&query, limit, offset))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let resp = AuditListResponse {
+        entries: entries.into_iter().map(AuditEntry::from).collect(),
+        total,
+        limit,
+        offset
+    };
+
+    Ok(HttpResponse::Ok().json(&resp))
+}
+
+async fn link_handler(req: HttpRequest, storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, conf : web::Data<crate::config::Config>, body : web::Bytes) -> Result<HttpResponse, Error> {
+    let link_id = req.match_info().get("id").unwrap().to_string();
+    return match req.method().as_str() {
+        "GET" => {
+            let lookup_id = link_id.clone();
+            let found = web::block(move || storage.find_link_with_clicks(&lookup_id))
+                .await?
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            match found {
+                Some((link, clicks)) => {
+                    let resp = UrlInfo {
+                        short_url: format!("{}/{}", &conf.hostname, link.id),
+                        link: Url::from(link),
+                        clicks
+                    };
+                    Ok(HttpResponse::Ok().json(&resp))
+                },
+                None => Ok(HttpResponse::NotFound().finish())
+            }
+        },
+        "PATCH" => {
+            let req_body : UrlUpdateRequest = serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?;
+            if let Some(url) = &req_body.url {
+                url::Url::parse(url).map_err(url_err_request)?;
+            }
+            if let Some(Some(status)) = req_body.redirect_type {
+                if !is_valid_redirect_type(status) {
+                    return Err(format!("redirect_type must be one of {:?}", REDIRECT_TYPES)).map_err(actix_web::error::ErrorBadRequest);
+                }
+            }
+            let now = Utc::now().naive_utc();
+            let expires_at = req_body.expiry(now).map_err(actix_web::error::ErrorBadRequest)?;
+
+            let changes = UrlDbUpdate {
+                url: req_body.url,
+                expires_at,
+                redirect_type: req_body.redirect_type.map(|val| val.map(|status| status as i32)),
+                title: req_body.title,
+                updated_at: now
+            };
+
+            let lookup_id = link_id.clone();
+            let owner = link_owner_filter(&req);
+            let audit = audit_context(&req, &conf);
+            let access = web::block(move || storage.update_link(&lookup_id, &changes, owner, &audit))
+                .await?
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            match access {
+                LinkAccess::Allowed => link_cache.invalidate(&link_id),
+                LinkAccess::NotFound => return Ok(HttpResponse::NotFound().finish()),
+                LinkAccess::Forbidden => return Ok(HttpResponse::Forbidden().finish())
+            }
+
+            Ok(HttpResponse::Ok().body(format!("{}/{}", &conf.hostname, link_id)))
+        },
+        _ => Ok(HttpResponse::MethodNotAllowed().finish())
+    }
+}
+
+/// Stores a click for the given short id in the background, so the redirect response is not held
+/// back by the insert.
+fn record_click(req: &HttpRequest, storage: web::Data<dyn Storage>, url_id: String) {
+    let header_value = |name: &str| {
+        req.headers().get(name)
+            .and_then(|val| val.to_str().ok())
+            .map(|val| val.to_owned())
+    };
+
+    let db_entry = ClickDbInsert {
+        url_id,
+        clicked_at: Utc::now().naive_utc(),
+        referrer: header_value("referer"),
+        user_agent: header_value("user-agent")
+    };
+
+    actix_web::rt::spawn(async move {
+        let db_resp = web::block(move || storage.record_click(&db_entry)).await;
+
+        match db_resp {
+            Ok(Err(err)) => warn!("Unable to record click: {}", err.err_msg()),
+            Err(err) => warn!("Unable to record click: {}", err),
+            _ => {}
+        }
+    });
+}
+
+async fn stats_handler(req: HttpRequest, storage: web::Data<dyn Storage>) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "GET" {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+    let link_id = req.match_info().get("id").unwrap().to_string();
+
+    let lookup_id = link_id.clone();
+    let days : Vec<(NaiveDate, i64)> = match web::block(move || storage.click_counts(&lookup_id))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)? {
+        Some(val) => val,
+        None => return Ok(HttpResponse::NotFound().finish())
+    };
+
+    let resp = ClickStats {
+        id: link_id,
+        total: days.iter().map(|(_, count)| count).sum(),
+        per_day: days.into_iter()
+            .map(|(day, count)| ClickDayCount { day, count })
+            .collect()
+    };
+
+    Ok(HttpResponse::Ok().json(&resp))
 }
 
 nano_id::gen!(
@@ -73,117 +462,104 @@ nano_id::gen!(
     b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ"
 );
 
-async fn new_url_handler(req: HttpRequest, pool: web::Data<DbPool>, conf : web::Data<crate::config::Config>, body : web::Bytes) -> Result<HttpResponse, Error> {
+async fn new_url_handler(req: HttpRequest, storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, conf : web::Data<crate::config::Config>, body : web::Bytes) -> Result<HttpResponse, Error> {
     if req.method().as_str() != "POST" {
         return Ok(HttpResponse::MethodNotAllowed().finish());
     }
     let req_body : UrlRequest = serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?;
     // Make sure the URL given in the request body is valid
     url::Url::parse(&req_body.url).map_err(url_err_request)?;
+    if let Some(status) = req_body.redirect_type {
+        if !is_valid_redirect_type(status) {
+            return Err(format!("redirect_type must be one of {:?}", REDIRECT_TYPES)).map_err(actix_web::error::ErrorBadRequest);
+        }
+    }
+    let now = Utc::now().naive_utc();
+    let expires_at = req_body.expiry(now).map_err(actix_web::error::ErrorBadRequest)?;
 
     let id = match req_body.id {
-        Some(val) => val,
-        None => url_id::<5>()
+        Some(val) => {
+            if !is_valid_custom_id(&val) {
+                return Err(format!("URL name can't be empty, contain '/', end with '+' or be one of {:?}", RESERVED_IDS)).map_err(actix_web::error::ErrorBadRequest);
+            }
+            val
+        },
+        None => loop {
+            let val = url_id::<5>();
+            if is_valid_custom_id(&val) {
+                break val;
+            }
+        }
     };
 
     let db_entry = UrlDbInsert {
         id: id.clone(),
-        url: req_body.url
-    };
-
-    let db_resp = web::block( move || {
-        let conn = pool.get().map_err(url_err_any)?;
-        diesel::insert_into(schema::urls::table)
-            .values(&db_entry)
-            .execute(&conn)
-            .map_err(url_err_any)
-    }).await?;
-    if let Err(val) = db_resp {
-        if let crate::model::error::Error::Any(inner_err) = &val {
-            let db_err : &diesel::result::Error = inner_err.downcast_ref().unwrap();
-            return if let diesel::result::Error::DatabaseError(kind, _) = db_err {
-                match kind {
-                    DatabaseErrorKind::UniqueViolation => {
-                        Err("URL name already in use. Try a different one".to_owned()).map_err(actix_web::error::ErrorBadRequest)
-                    }
-                    _ => {
-                        println!("{}", db_err.to_string());
-                        Err("Database error".to_owned()).map_err(actix_web::error::ErrorInternalServerError)
-                    }
-                }
-            } else {
-                println!("{}", val.err_msg());
-                Err(val).map_err(actix_web::error::ErrorInternalServerError)
-            }
-        }
+        url: req_body.url,
+        expires_at,
+        redirect_type: req_body.redirect_type.map(|val| val as i32),
+        created_at: now,
+        created_by: req.extensions().get::<Principal>().map(|principal| principal.key_id),
+        title: req_body.title
+    };
+
+    let audit = audit_context(&req, &conf);
+    let created = web::block(move || storage.create_link(&db_entry, &audit))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !created {
+        return Err("URL name already in use. Try a different one".to_owned()).map_err(actix_web::error::ErrorBadRequest);
     }
+    // The id may have been requested before it existed
+    link_cache.invalidate(&id);
 
     Ok(HttpResponse::Ok().body(format!("{}/{}", &conf.hostname, id)))
 }
 
-async fn delete_url_handler(req: HttpRequest, pool: web::Data<DbPool>, body : web::Bytes) -> Result<HttpResponse, Error> {
-    use crate::schema::urls::*;
-    use crate::schema::urls::dsl::urls;
-
+async fn delete_url_handler(req: HttpRequest, storage: web::Data<dyn Storage>, link_cache : web::Data<LinkCache>, conf : web::Data<crate::config::Config>, body : web::Bytes) -> Result<HttpResponse, Error> {
     if req.method().as_str() != "DELETE" {
         return Ok(HttpResponse::MethodNotAllowed().finish());
     }
     let req_body : UrlDeleteRequest = serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?;
+    let owner = link_owner_filter(&req);
+    let audit = audit_context(&req, &conf);
 
-    let db_resp = web::block( move || {
-        let conn = pool.get().map_err(url_err_any)?;
-        diesel::delete(urls.filter(id.eq(req_body.id)))
-            .execute(&conn)
-            .map_err(url_err_any)
-    }).await?;
-    if let Err(val) = db_resp {
-        if let crate::model::error::Error::Any(inner_err) = &val {
-            let db_err : &diesel::result::Error = inner_err.downcast_ref().unwrap();
-            return if let diesel::result::Error::DatabaseError(kind, _) = db_err {
-                println!("{}", db_err.to_string());
-                Err("Database error".to_owned()).map_err(actix_web::error::ErrorInternalServerError)
-            } else {
-                println!("{}", val.err_msg());
-                Err(val).map_err(actix_web::error::ErrorInternalServerError)
-            }
-        }
-    } else {
-        if db_resp.unwrap() == 0 {
-            return Ok(HttpResponse::NotFound().finish());
-        }
+    let link_id = req_body.id.clone();
+    let access = web::block(move || storage.delete_link(&req_body.id, owner, &audit))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    match access {
+        LinkAccess::Allowed => link_cache.invalidate(&link_id),
+        LinkAccess::NotFound => return Ok(HttpResponse::NotFound().finish()),
+        LinkAccess::Forbidden => return Ok(HttpResponse::Forbidden().finish())
     }
 
     Ok(HttpResponse::Ok().finish())
 }
 
-nano_id::gen!(
-    api_key,
-    86,
-    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ&*/@!#$%^()-_=+[]{};:,.?"
-);
+/// Who is making the request, for the audit log
+fn audit_context(req: &HttpRequest, conf: &crate::config::Config) -> AuditContext {
+    AuditContext {
+        actor_key_id: req.extensions().get::<Principal>().map(|principal| principal.key_id),
+        ip: client_ip(req.headers(), req.peer_addr(), ForwardedIp::from_conf(conf).as_ref())
+    }
+}
+
+/// Owner filter for link management, see `Principal::link_owner_filter`
+fn link_owner_filter(req: &HttpRequest) -> Option<i64> {
+    req.extensions().get::<Principal>()
+        .and_then(|principal| principal.link_owner_filter())
+}
 
-async fn key_handler(req: HttpRequest, pool: web::Data<DbPool>, body : web::Bytes) -> Result<HttpResponse, Error> {
-    use crate::model::api_key::db::api_keys::*;
-    use crate::model::api_key::db::api_keys::dsl::api_keys;
+async fn key_handler(req: HttpRequest, storage: web::Data<dyn Storage>, conf : web::Data<crate::config::Config>, key_cache : web::Data<KeyCache>, body : web::Bytes) -> Result<HttpResponse, Error> {
     return match req.method().as_str() {
         "GET" => {
 
-            let keys : Vec<ApiKeyDb> = web::block(move || {
-                let conn = pool.get().map_err(url_err_any)?;
-                api_keys
-                    .load::<ApiKeyDb>(&conn)
-                    .map_err(url_err_any)
-            }).await?
+            let keys = web::block(move || storage.list_keys()).await?
                 .map_err(actix_web::error::ErrorInternalServerError)?;
 
-           let keys_resp : Vec<ApiKey> = keys.iter()
-                .map(|a| {
-                    ApiKey {
-                        id: a.id,
-                        key: a.key.clone(),
-                        description: a.description.clone()
-                    }
-                }).collect();
+           let keys_resp : Vec<ApiKey> = keys.into_iter()
+                .map(ApiKey::from)
+                .collect();
 
 
             Ok(HttpResponse::Ok().json(&keys_resp))
@@ -191,23 +567,18 @@ async fn key_handler(req: HttpRequest, pool: web::Data<DbPool>, body : web::Byte
         "POST" => {
             let req_body : ApiKeyPostRequest = serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?;
 
-            let new_key = api_key::<64>();
-            let db_entry = ApiKeyDbInsert {
-                key: new_key.clone(),
-                description: req_body.description
-            };
+            let key_expires_at = req_body.expiry(Utc::now().naive_utc()).map_err(actix_web::error::ErrorBadRequest)?;
+            let key_scopes = req_body.scopes.unwrap_or_else(|| Scope::DEFAULT.to_vec());
+            let new_key = generate_key();
+            let db_entry = ApiKeyDbInsert::new(&new_key, req_body.description, &key_scopes, key_expires_at);
 
-            web::block(move || {
-                let conn = pool.get().map_err(url_err_any)?;
-                diesel::insert_into(crate::model::api_key::db::api_keys::table)
-                    .values(&db_entry)
-                    .execute(&conn)
-                    .map_err(url_err_any)
-            }).await?
+            let audit = audit_context(&req, &conf);
+            let new_id = web::block(move || storage.create_key(&db_entry, &audit)).await?
                 .map_err(actix_web::error::ErrorInternalServerError)?;
 
 
             let resp = ApiKeyPostResponse {
+                id: new_id,
                 key: new_key
             };
 
@@ -216,26 +587,12 @@ async fn key_handler(req: HttpRequest, pool: web::Data<DbPool>, body : web::Byte
         "DELETE" => {
             let req_body : ApiKeyDeleteRequest = serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?;
 
-            let moved_pool = pool.clone();
-            let keys : Vec<ApiKeyDb> = web::block(move || {
-                let conn = moved_pool.get().map_err(url_err_any)?;
-                api_keys
-                    .filter(key.eq(req_body.key))
-                    .limit(1)
-                    .load::<ApiKeyDb>(&conn)
-                    .map_err(url_err_any)
-            }).await?
+            let audit = audit_context(&req, &conf);
+            let deleted = web::block(move || storage.delete_key(req_body.id, &audit)).await?
                 .map_err(actix_web::error::ErrorInternalServerError)?;
 
-
-            return if keys.len() > 0 {
-                let api_key_entry = keys.first().unwrap().clone();
-                web::block(move || {
-                    let conn = pool.get().map_err(url_err_any)?;
-                    diesel::delete(api_keys.filter(key.eq(&api_key_entry.key)))
-                        .execute(&conn)
-                        .map_err(url_err_any)
-                }).await?.map_err(actix_web::error::ErrorInternalServerError)?;;
+            return if deleted {
+                key_cache.invalidate(req_body.id);
 
                 Ok(HttpResponse::Ok().finish())
             } else {
@@ -244,4 +601,60 @@ async fn key_handler(req: HttpRequest, pool: web::Data<DbPool>, body : web::Byte
         },
         _ => Ok(HttpResponse::MethodNotAllowed().finish())
     }
+}
+
+/// Issues a new secret for an existing key, keeping its id and with it the links it owns
+async fn key_rotate_handler(req: HttpRequest, storage: web::Data<dyn Storage>, conf : web::Data<crate::config::Config>, key_cache : web::Data<KeyCache>, body : web::Bytes) -> Result<HttpResponse, Error> {
+    if req.method().as_str() != "POST" {
+        return Ok(HttpResponse::MethodNotAllowed().finish())
+    }
+    let key_id = match req.match_info().get("id").unwrap().parse::<i64>() {
+        Ok(val) => val,
+        Err(_) => return Ok(HttpResponse::NotFound().finish())
+    };
+    let req_body : ApiKeyRotateRequest = if body.is_empty() {
+        ApiKeyRotateRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?
+    };
+
+    let now = Utc::now().naive_utc();
+    let grace_until = match req_body.grace_seconds {
+        Some(secs) => Some(offset_seconds(now, secs, "grace_seconds").map_err(actix_web::error::ErrorBadRequest)?),
+        None => None
+    };
+
+    let new_key = generate_key();
+    let moved_key = new_key.clone();
+    let audit = audit_context(&req, &conf);
+    let rotated = web::block(move || storage.rotate_key(key_id, &moved_key, grace_until, &audit)).await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if !rotated {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    key_cache.invalidate(key_id);
+
+    Ok(HttpResponse::Ok().json(&ApiKeyPostResponse {
+        id: key_id,
+        key: new_key
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_segments_are_reserved() {
+        // Every route registered above whose first segment is fixed, so new routes can't be left out of RESERVED_IDS
+        let segments : Vec<&str> = include_str!("web.rs").split("web::resource(\"/").skip(1)
+            .map(|rest| rest.split(['/', '"']).next().unwrap())
+            .filter(|segment| !segment.starts_with('{'))
+            .collect();
+        assert!(segments.contains(&"stats"));
+        for segment in segments {
+            assert!(!is_valid_custom_id(segment), "{} can be claimed as a short id", segment);
+        }
+    }
 }
\ No newline at end of file
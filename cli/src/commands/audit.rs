@@ -0,0 +1,91 @@
+use crate::commands::{CommandData, new_runtime};
+use serde::{Serialize, Deserialize};
+
+pub struct Audit;
+
+impl<'a> Audit {
+    pub fn handle(context : CommandData) {
+        // Command logic
+        new_runtime().block_on(async move {
+            let client = reqwest::Client::new();
+
+            let mut query : Vec<(&str, String)> = Vec::new();
+            for arg in ["actor", "action", "target", "since", "until", "limit", "offset"] {
+                if let Some(val) = context.arg_matches.value_of(arg) {
+                    query.push((arg, val.to_owned()));
+                }
+            }
+
+            let resp = match client.get(format!("{}/audit", context.conf.api_endpoint))
+                .header("x-api-key", context.conf.get_api_key().unwrap())
+                .query(&query)
+                .send()
+                .await {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            match resp.status().as_u16() {
+                401 => {
+                    println!("{}", "Unauthorised");
+                    return ();
+                },
+                403 => {
+                    println!("{}", "Forbidden, the API key lacks the required scope");
+                    return ();
+                },
+                200 => {},
+                _ => {
+                    println!("{}", resp.text().await.unwrap());
+                    return ();
+                }
+            }
+
+            let resp_bytes = resp.bytes().await.unwrap();
+            let list : AuditList = serde_json::from_slice(resp_bytes.as_ref()).unwrap();
+
+            for entry in &list.entries {
+                let actor = entry.actor_key_id.map(|val| format!("key {}", val)).unwrap_or("unknown key".to_owned());
+                println!("{} - {} by {} from {} - {}", entry.created_at, entry.action, actor, entry.ip.as_deref().unwrap_or("unknown address"), entry.target_id);
+                if let Some(old_value) = &entry.old_value {
+                    println!("    old: {}", old_value);
+                }
+                if let Some(new_value) = &entry.new_value {
+                    println!("    new: {}", new_value);
+                }
+            }
+
+            if list.entries.is_empty() {
+                println!("No audit log entries found ({} total)", list.total);
+            } else {
+                println!("Showing {}-{} of {}", list.offset + 1, list.offset + list.entries.len() as i64, list.total);
+            }
+
+            ()
+        });
+
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditList {
+    pub entries : Vec<AuditEntry>,
+    pub total : i64,
+    pub limit : i64,
+    pub offset : i64
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id : i64,
+    pub actor_key_id : Option<i64>,
+    pub action : String,
+    pub target_id : String,
+    pub old_value : Option<serde_json::Value>,
+    pub new_value : Option<serde_json::Value>,
+    pub created_at : String,
+    pub ip : Option<String>
+}
\ No newline at end of file
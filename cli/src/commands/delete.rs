@@ -32,6 +32,11 @@ impl<'a> Delete {
                 return ();
             }
 
+            if resp.status().eq(&403) {
+                println!("{}", "Forbidden, the API key lacks the required scope or the short URL belongs to another API key");
+                return ();
+            }
+
             println!("{}", resp.text().await.unwrap());
             ()
         });
@@ -0,0 +1,108 @@
+use crate::commands::{CommandData, new_runtime, redirect_type_arg, ttl_arg};
+use serde::{Serialize, Deserialize};
+
+pub struct Edit;
+
+impl<'a> Edit {
+    pub fn handle(context : CommandData) {
+        // Command logic
+        new_runtime().block_on(async move {
+            let client = reqwest::Client::new();
+
+            let ttl_seconds = match ttl_arg(&context.arg_matches) {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            let redirect_type = match redirect_type_arg(&context.arg_matches) {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            let req_data = RequestData {
+                url: context.arg_matches.value_of("URL").map(|val| val.to_owned()),
+                expires_at: if context.arg_matches.is_present("no-expiry") {
+                    Some(None)
+                } else {
+                    context.arg_matches.value_of("expires-at").map(|val| Some(val.to_owned()))
+                },
+                ttl_seconds,
+                redirect_type: if context.arg_matches.is_present("default-status") {
+                    Some(None)
+                } else {
+                    redirect_type.map(Some)
+                },
+                title: if context.arg_matches.is_present("no-title") {
+                    Some(None)
+                } else {
+                    context.arg_matches.value_of("title").map(|val| Some(val.to_owned()))
+                }
+            };
+
+            if req_data.is_empty() {
+                println!("{}", "Nothing to change");
+                return ();
+            }
+
+            let name = context.arg_matches.value_of("NAME").unwrap();
+            let resp = match client.patch(format!("{}/links/{}", context.conf.api_endpoint, name))
+                .header("x-api-key", context.conf.get_api_key().unwrap())
+                .json(&req_data)
+                .send()
+                .await {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            match resp.status().as_u16() {
+                401 => {
+                    println!("{}", "Unauthorised");
+                    return ();
+                },
+                403 => {
+                    println!("{}", "Forbidden, the API key lacks the required scope or the short URL belongs to another API key");
+                    return ();
+                },
+                404 => {
+                    println!("Short URL '{}' not found", name);
+                    return ();
+                },
+                _ => {}
+            }
+
+            println!("{}", resp.text().await.unwrap());
+            ()
+        });
+
+    }
+}
+
+// Fields left out are kept as-is by the server, while `Some(None)` is sent as an explicit null to clear the value
+#[derive(Serialize, Deserialize)]
+struct RequestData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at : Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl_seconds : Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect_type : Option<Option<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title : Option<Option<String>>
+}
+
+impl RequestData {
+    fn is_empty(&self) -> bool {
+        self.url.is_none() && self.expires_at.is_none() && self.ttl_seconds.is_none() && self.redirect_type.is_none() && self.title.is_none()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,72 @@
+use crate::commands::{CommandData, new_runtime};
+use crate::commands::list::Link;
+use serde::{Serialize, Deserialize};
+
+pub struct Info;
+
+impl<'a> Info {
+    pub fn handle(context : CommandData) {
+        // Command logic
+        new_runtime().block_on(async move {
+            let client = reqwest::Client::new();
+
+            let name = context.arg_matches.value_of("NAME").unwrap();
+            let resp = match client.get(format!("{}/links/{}", context.conf.api_endpoint, name))
+                .header("x-api-key", context.conf.get_api_key().unwrap())
+                .send()
+                .await {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            match resp.status().as_u16() {
+                401 => {
+                    println!("{}", "Unauthorised");
+                    return ();
+                },
+                403 => {
+                    println!("{}", "Forbidden, the API key lacks the required scope");
+                    return ();
+                },
+                404 => {
+                    println!("Short URL '{}' not found", name);
+                    return ();
+                },
+                200 => {},
+                _ => {
+                    println!("{}", resp.text().await.unwrap());
+                    return ();
+                }
+            }
+
+            let resp_bytes = resp.bytes().await.unwrap();
+            let info : LinkInfo = serde_json::from_slice(resp_bytes.as_ref()).unwrap();
+            let link = info.link;
+
+            println!("Name:        {}", link.id);
+            println!("Short URL:   {}", info.short_url);
+            println!("Target:      {}", link.url);
+            println!("Title:       {}", link.title.unwrap_or("".to_owned()));
+            println!("Redirect:    {}", link.redirect_type.map_or("server default".to_owned(), |val| val.to_string()));
+            println!("Expires:     {}", link.expires_at.unwrap_or("never".to_owned()));
+            println!("Created:     {}", link.created_at);
+            println!("Updated:     {}", link.updated_at.unwrap_or("never".to_owned()));
+            println!("Created by:  {}", link.created_by.map_or("unknown".to_owned(), |val| format!("API key {}", val)));
+            println!("Clicks:      {}", info.clicks);
+            println!("Preview:     {}+", info.short_url);
+            ()
+        });
+
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LinkInfo {
+    #[serde(flatten)]
+    pub link : Link,
+    pub short_url : String,
+    pub clicks : i64
+}
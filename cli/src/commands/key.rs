@@ -1,4 +1,4 @@
-use crate::commands::{CommandData, new_runtime};
+use crate::commands::{CommandData, new_runtime, ttl_arg};
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
 use reqwest::{Error, Response};
@@ -28,11 +28,24 @@ impl<'a> Key {
                         return ();
                     }
 
+                    if resp.status().eq(&403) {
+                        println!("{}", "Forbidden, the API key lacks the required scope");
+                        return ();
+                    }
+
                     let resp_bytes = resp.bytes().await.unwrap();
                     let keys : Vec<ApiKey> = serde_json::from_slice(resp_bytes.as_ref()).unwrap();
 
                     for key in keys {
-                        println!("Key: '{}' - \"{}\"", key.key, key.description.unwrap_or("".to_owned()))
+                        // Records of JWT subjects have no key of their own, their scopes come from the token
+                        if let Some(subject) = key.subject {
+                            println!("ID: {} - Subject: '{}' - Created: {} - Last used: {}",
+                                     key.id, subject, key.created_at, key.last_used_at.unwrap_or("never".to_owned()));
+                            continue;
+                        }
+                        println!("ID: {} - Key: '{}...' - \"{}\" - Scopes: {} - Created: {} - Expires: {} - Last used: {}",
+                                 key.id, key.key_prefix, key.description.unwrap_or("".to_owned()), key.scopes.join(","),
+                                 key.created_at, key.expires_at.unwrap_or("never".to_owned()), key.last_used_at.unwrap_or("never".to_owned()))
                     }
 
                     ()
@@ -42,8 +55,19 @@ impl<'a> Key {
                 new_runtime().block_on(async move {
                     let client = reqwest::Client::new();
 
+                    let ttl_seconds = match ttl_arg(sub_matches) {
+                        Ok(val) => val,
+                        Err(err) => {
+                            println!("{}", err);
+                            return ();
+                        }
+                    };
+
                     let req_data = CreateRequestData {
-                        description: sub_matches.value_of("description").unwrap_or("").to_owned()
+                        description: sub_matches.value_of("description").unwrap_or("").to_owned(),
+                        scopes: sub_matches.values_of("scope").map(|vals| vals.map(|val| val.to_owned()).collect()),
+                        expires_at: sub_matches.value_of("expires-at").map(|val| val.to_owned()),
+                        ttl_seconds
                     };
 
                     let resp = match client.post(format!("{}/key", context.conf.api_endpoint))
@@ -63,7 +87,93 @@ impl<'a> Key {
                         return ();
                     }
 
-                    println!("{}", resp.text().await.unwrap());
+                    if resp.status().eq(&403) {
+                        println!("{}", "Forbidden, the API key lacks the required scope");
+                        return ();
+                    }
+
+                    let resp_bytes = resp.bytes().await.unwrap();
+                    let created : CreateResponseData = match serde_json::from_slice(resp_bytes.as_ref()) {
+                        Ok(val) => val,
+                        Err(_) => {
+                            println!("{}", String::from_utf8_lossy(resp_bytes.as_ref()));
+                            return ();
+                        }
+                    };
+
+                    println!("ID: {}", created.id);
+                    println!("Key: {}", created.key);
+                    println!("{}", "Store the key somewhere safe, it can't be shown again");
+                    ()
+                });
+            },
+            Some(("rotate", sub_matches)) => {
+                new_runtime().block_on(async move {
+                    let client = reqwest::Client::new();
+
+                    let id = match sub_matches.value_of("ID").unwrap_or("").parse::<i64>() {
+                        Ok(val) => val,
+                        Err(_) => {
+                            println!("{}", "ID must be a number, as shown by 'url key list'");
+                            return ();
+                        }
+                    };
+                    let grace_seconds = match sub_matches.value_of("grace").map(|val| val.parse::<i64>()) {
+                        Some(Ok(val)) => Some(val),
+                        Some(Err(_)) => {
+                            println!("{}", "Grace period must be a whole number of seconds");
+                            return ();
+                        },
+                        None => None
+                    };
+                    let req_data = RotateRequestData {
+                        grace_seconds
+                    };
+
+                    let resp = match client.post(format!("{}/key/{}/rotate", context.conf.api_endpoint, id))
+                        .header("x-api-key", context.conf.get_api_key().unwrap())
+                        .json(&req_data)
+                        .send()
+                        .await {
+                        Ok(val) => val,
+                        Err(err) => {
+                            println!("{}", err);
+                            return ();
+                        }
+                    };
+
+                    match resp.status().as_u16() {
+                        401 => {
+                            println!("{}", "Unauthorised");
+                            return ();
+                        },
+                        403 => {
+                            println!("{}", "Forbidden, the API key lacks the required scope");
+                            return ();
+                        },
+                        404 => {
+                            println!("Key not found");
+                            return ();
+                        }
+                        _ => {}
+                    }
+
+                    let resp_bytes = resp.bytes().await.unwrap();
+                    let rotated : CreateResponseData = match serde_json::from_slice(resp_bytes.as_ref()) {
+                        Ok(val) => val,
+                        Err(_) => {
+                            println!("{}", String::from_utf8_lossy(resp_bytes.as_ref()));
+                            return ();
+                        }
+                    };
+
+                    println!("ID: {}", rotated.id);
+                    println!("Key: {}", rotated.key);
+                    match grace_seconds {
+                        Some(secs) if secs > 0 => println!("The old key keeps working for {} more seconds", secs),
+                        _ => println!("{}", "The old key no longer works")
+                    }
+                    println!("{}", "Store the key somewhere safe, it can't be shown again");
                     ()
                 });
             },
@@ -71,8 +181,15 @@ impl<'a> Key {
                 new_runtime().block_on(async move {
                     let client = reqwest::Client::new();
 
+                    let id = match sub_matches.value_of("ID").unwrap_or("").parse::<i64>() {
+                        Ok(val) => val,
+                        Err(_) => {
+                            println!("{}", "ID must be a number, as shown by 'url key list'");
+                            return ();
+                        }
+                    };
                     let req_data = DeleteRequestData {
-                        key: sub_matches.value_of("Key").unwrap_or("").to_owned()
+                        id
                     };
 
                     let resp = match client.delete(format!("{}/key", context.conf.api_endpoint))
@@ -91,7 +208,11 @@ impl<'a> Key {
                         401 => {
                             println!("{}", "Unauthorised");
                             return ();
-                        }
+                        },
+                        403 => {
+                            println!("{}", "Forbidden, the API key lacks the required scope");
+                            return ();
+                        },
                         200 => {
                             println!("Key deleted");
                             return ();
@@ -100,10 +221,11 @@ impl<'a> Key {
                             println!("Key not found");
                             return ();
                         }
-                        _ => {
-
-                        }
+                        _ => {}
                     }
+
+                    println!("Unable to delete key: {}", resp.status());
+                    println!("{}", resp.text().await.unwrap());
                     ()
                 });
             },
@@ -114,17 +236,36 @@ impl<'a> Key {
 
 #[derive(Serialize, Deserialize)]
 struct CreateRequestData {
-    description : String
+    description : String,
+    scopes : Option<Vec<String>>,
+    expires_at : Option<String>,
+    ttl_seconds : Option<i64>
 }
 
 #[derive(Serialize, Deserialize)]
-struct DeleteRequestData {
+struct CreateResponseData {
+    id : i64,
     key : String
 }
 
+#[derive(Serialize, Deserialize)]
+struct RotateRequestData {
+    grace_seconds : Option<i64>
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeleteRequestData {
+    id : i64
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ApiKey {
     pub id : i64,
-    pub key : String,
-    pub description : Option<String>
+    pub key_prefix : String,
+    pub description : Option<String>,
+    pub scopes : Vec<String>,
+    pub expires_at : Option<String>,
+    pub created_at : String,
+    pub last_used_at : Option<String>,
+    pub subject : Option<String>
 }
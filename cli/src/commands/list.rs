@@ -0,0 +1,84 @@
+use crate::commands::{CommandData, new_runtime};
+use serde::{Serialize, Deserialize};
+
+pub struct List;
+
+impl<'a> List {
+    pub fn handle(context : CommandData) {
+        // Command logic
+        new_runtime().block_on(async move {
+            let client = reqwest::Client::new();
+
+            let mut query : Vec<(&str, String)> = Vec::new();
+            for arg in ["search", "limit", "offset"] {
+                if let Some(val) = context.arg_matches.value_of(arg) {
+                    query.push((arg, val.to_owned()));
+                }
+            }
+
+            let resp = match client.get(format!("{}/links", context.conf.api_endpoint))
+                .header("x-api-key", context.conf.get_api_key().unwrap())
+                .query(&query)
+                .send()
+                .await {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            match resp.status().as_u16() {
+                401 => {
+                    println!("{}", "Unauthorised");
+                    return ();
+                },
+                403 => {
+                    println!("{}", "Forbidden, the API key lacks the required scope");
+                    return ();
+                },
+                200 => {},
+                _ => {
+                    println!("{}", resp.text().await.unwrap());
+                    return ();
+                }
+            }
+
+            let resp_bytes = resp.bytes().await.unwrap();
+            let list : LinkList = serde_json::from_slice(resp_bytes.as_ref()).unwrap();
+
+            for link in &list.links {
+                println!("{} -> {} (created {})", link.id, link.url, link.created_at)
+            }
+
+            if list.links.is_empty() {
+                println!("No short URLs found ({} total)", list.total);
+            } else {
+                println!("Showing {}-{} of {}", list.offset + 1, list.offset + list.links.len() as i64, list.total);
+            }
+
+            ()
+        });
+
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LinkList {
+    pub links : Vec<Link>,
+    pub total : i64,
+    pub limit : i64,
+    pub offset : i64
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Link {
+    pub id : String,
+    pub url : String,
+    pub expires_at : Option<String>,
+    pub redirect_type : Option<u16>,
+    pub updated_at : Option<String>,
+    pub created_at : String,
+    pub created_by : Option<i64>,
+    pub title : Option<String>
+}
@@ -5,6 +5,11 @@ use crate::config::Config;
 pub mod new;
 pub mod delete;
 pub mod key;
+pub mod edit;
+pub mod list;
+pub mod info;
+pub mod qr;
+pub mod audit;
 
 pub struct CommandData<'a> {
     app: Command<'a>,
@@ -27,4 +32,26 @@ pub fn new_runtime() -> Runtime {
         .thread_name("seqtf_url-worker")
         .enable_all()
         .build().unwrap()
+}
+
+/// Parses the optional `--ttl` argument of commands that create or modify short URLs
+pub fn ttl_arg(matches : &ArgMatches) -> Result<Option<i64>, String> {
+    match matches.value_of("ttl").map(|val| val.parse::<i64>()) {
+        Some(Ok(val)) => Ok(Some(val)),
+        Some(Err(_)) => Err("TTL must be a whole number of seconds".to_owned()),
+        None => Ok(None)
+    }
+}
+
+/// Parses the optional `--permanent` and `--status` arguments of commands that create or modify short URLs
+pub fn redirect_type_arg(matches : &ArgMatches) -> Result<Option<u16>, String> {
+    if matches.is_present("permanent") {
+        return Ok(Some(308));
+    }
+
+    match matches.value_of("status").map(|val| val.parse::<u16>()) {
+        Some(Ok(val)) => Ok(Some(val)),
+        Some(Err(_)) => Err("Status must be one of 301, 302, 303, 307 or 308".to_owned()),
+        None => Ok(None)
+    }
 }
\ No newline at end of file
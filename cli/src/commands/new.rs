@@ -1,4 +1,4 @@
-use crate::commands::{CommandData, new_runtime};
+use crate::commands::{CommandData, new_runtime, redirect_type_arg, ttl_arg};
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
 use reqwest::{Error, Response};
@@ -11,11 +11,31 @@ impl<'a> New {
         new_runtime().block_on(async move {
             let client = reqwest::Client::new();
 
+            let ttl_seconds = match ttl_arg(&context.arg_matches) {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            let redirect_type = match redirect_type_arg(&context.arg_matches) {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
             let req_data = RequestData {
                 url: context.arg_matches.value_of("URL").unwrap().to_owned(),
                 id: context.arg_matches.value_of("name").map_or(None, |val| {
                     Some(val.to_owned())
-                })
+                }),
+                expires_at: context.arg_matches.value_of("expires-at").map(|val| val.to_owned()),
+                ttl_seconds,
+                redirect_type,
+                title: context.arg_matches.value_of("title").map(|val| val.to_owned())
             };
 
             let resp = match client.post(format!("{}/new", context.conf.api_endpoint))
@@ -35,6 +55,11 @@ impl<'a> New {
                 return ();
             }
 
+            if resp.status().eq(&403) {
+                println!("{}", "Forbidden, the API key lacks the required scope");
+                return ();
+            }
+
             println!("{}", resp.text().await.unwrap());
             ()
         });
@@ -45,5 +70,9 @@ impl<'a> New {
 #[derive(Serialize, Deserialize)]
 struct RequestData {
     url : String,
-    id : Option<String>
+    id : Option<String>,
+    expires_at : Option<String>,
+    ttl_seconds : Option<i64>,
+    redirect_type : Option<u16>,
+    title : Option<String>
 }
\ No newline at end of file
@@ -0,0 +1,122 @@
+use crate::commands::{CommandData, new_runtime};
+use crate::commands::info::LinkInfo;
+use qrcode::QrCode;
+use qrcode::render::unicode;
+
+pub struct Qr;
+
+impl<'a> Qr {
+    pub fn handle(context : CommandData) {
+        // Command logic
+        new_runtime().block_on(async move {
+            let client = reqwest::Client::new();
+            let name = context.arg_matches.value_of("NAME").unwrap();
+
+            let out = match context.arg_matches.value_of("out") {
+                Some(val) => val,
+                None => {
+                    // Render in the terminal, which only needs the full short URL from the server
+                    let resp = match client.get(format!("{}/links/{}", context.conf.api_endpoint, name))
+                        .header("x-api-key", context.conf.get_api_key().unwrap())
+                        .send()
+                        .await {
+                        Ok(val) => val,
+                        Err(err) => {
+                            println!("{}", err);
+                            return ();
+                        }
+                    };
+
+                    match resp.status().as_u16() {
+                        401 => {
+                            println!("{}", "Unauthorised");
+                            return ();
+                        },
+                        403 => {
+                            println!("{}", "Forbidden, the API key lacks the required scope");
+                            return ();
+                        },
+                        404 => {
+                            println!("Short URL '{}' not found", name);
+                            return ();
+                        },
+                        200 => {},
+                        _ => {
+                            println!("{}", resp.text().await.unwrap());
+                            return ();
+                        }
+                    }
+
+                    let resp_bytes = resp.bytes().await.unwrap();
+                    let info : LinkInfo = serde_json::from_slice(resp_bytes.as_ref()).unwrap();
+                    let code = match QrCode::new(info.short_url.as_bytes()) {
+                        Ok(val) => val,
+                        Err(err) => {
+                            println!("{}", err);
+                            return ();
+                        }
+                    };
+
+                    let rendered = code.render::<unicode::Dense1x2>()
+                        .dark_color(unicode::Dense1x2::Light)
+                        .light_color(unicode::Dense1x2::Dark)
+                        .build();
+                    println!("{}", rendered);
+                    println!("{}", info.short_url);
+                    return ();
+                }
+            };
+
+            let format = match context.arg_matches.value_of("format") {
+                Some(val) => val.to_owned(),
+                None => {
+                    if out.to_lowercase().ends_with(".svg") {
+                        "svg".to_owned()
+                    } else {
+                        "png".to_owned()
+                    }
+                }
+            };
+
+            let mut query = vec![("format", format)];
+            if let Some(size) = context.arg_matches.value_of("size") {
+                query.push(("size", size.to_owned()));
+            }
+
+            let resp = match client.get(format!("{}/{}/qr", context.conf.api_endpoint, name))
+                .query(&query)
+                .send()
+                .await {
+                Ok(val) => val,
+                Err(err) => {
+                    println!("{}", err);
+                    return ();
+                }
+            };
+
+            match resp.status().as_u16() {
+                404 => {
+                    println!("Short URL '{}' not found", name);
+                    return ();
+                },
+                410 => {
+                    println!("Short URL '{}' has expired", name);
+                    return ();
+                },
+                200 => {},
+                _ => {
+                    println!("{}", resp.text().await.unwrap());
+                    return ();
+                }
+            }
+
+            let resp_bytes = resp.bytes().await.unwrap();
+            match std::fs::write(out, &resp_bytes) {
+                Ok(_) => println!("QR code written to {}", out),
+                Err(err) => println!("Unable to write QR code to {}: {}", out, err)
+            }
+            ()
+        });
+
+    }
+}
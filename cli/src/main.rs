@@ -1,7 +1,12 @@
 use clap::{arg, command, Command};
 use crate::commands::CommandData;
+use crate::commands::audit::Audit;
 use crate::commands::delete::Delete;
+use crate::commands::edit::Edit;
+use crate::commands::info::Info;
 use crate::commands::key::Key;
+use crate::commands::list::List;
+use crate::commands::qr::Qr;
 use crate::commands::new::New;
 
 mod config;
@@ -19,9 +24,30 @@ fn main() {
                 .about("Create new short URL")
                 .arg_required_else_help(true)
                 .arg(arg!(-n --"name" <NAME> "Optional custom name").required(false))
+                .arg(arg!(-t --"ttl" <SECONDS> "Optionally expire the short URL after the given amount of seconds").required(false))
+                .arg(arg!(--"expires-at" <TIMESTAMP> "Optionally expire the short URL at the given RFC 3339 timestamp. Example: '2022-06-01T00:00:00Z'").required(false))
+                .arg(arg!(-p --"permanent" "Redirect with 308 Permanent Redirect instead of the server default"))
+                .arg(arg!(-s --"status" <CODE> "Redirect status code to use. One of 301, 302, 303, 307 or 308").required(false).conflicts_with("permanent"))
+                .arg(arg!(--"title" <TITLE> "Optional title shown on the preview page of the short URL. The server doesn't fetch the target page's title, so without one the preview shows the target host").required(false))
                 .arg(arg!(-a --"api-key" <APIKEY> "Optionally specify API key. Can also be set via environment variable (SEQ_URL_API_KEY) or config file").required(false))
                 .arg(arg!([URL]))
 
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Change an existing short URL")
+                .arg_required_else_help(true)
+                .arg(arg!(-t --"ttl" <SECONDS> "Expire the short URL after the given amount of seconds").required(false))
+                .arg(arg!(--"expires-at" <TIMESTAMP> "Expire the short URL at the given RFC 3339 timestamp. Example: '2022-06-01T00:00:00Z'").required(false))
+                .arg(arg!(--"no-expiry" "Remove any expiry from the short URL").conflicts_with_all(&["ttl", "expires-at"]))
+                .arg(arg!(-p --"permanent" "Redirect with 308 Permanent Redirect").conflicts_with("default-status"))
+                .arg(arg!(-s --"status" <CODE> "Redirect status code to use. One of 301, 302, 303, 307 or 308").required(false).conflicts_with_all(&["permanent", "default-status"]))
+                .arg(arg!(--"default-status" "Redirect with the server default status code"))
+                .arg(arg!(--"title" <TITLE> "Title shown on the preview page of the short URL, instead of the target host. The target page's title isn't fetched").required(false))
+                .arg(arg!(--"no-title" "Remove the title of the short URL").conflicts_with("title"))
+                .arg(arg!([NAME]))
+                .arg(arg!([URL] "New target URL"))
+
         )
         .subcommand(
             Command::new("delete")
@@ -31,6 +57,39 @@ fn main() {
                 .arg(arg!([NAME]))
 
         )
+        .subcommand(
+            Command::new("list")
+                .about("List short URLs, newest first")
+                .arg(arg!(-s --"search" <TEXT> "Only show short URLs whose name or target contains the given text").required(false))
+                .arg(arg!(-l --"limit" <COUNT> "Maximum amount of short URLs to show").required(false))
+                .arg(arg!(-o --"offset" <COUNT> "Amount of short URLs to skip, for paging through results").required(false))
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Show details of a short URL")
+                .arg_required_else_help(true)
+                .arg(arg!([NAME]))
+        )
+        .subcommand(
+            Command::new("qr")
+                .about("Show a QR code of a short URL in the terminal, or save it to a file")
+                .arg_required_else_help(true)
+                .arg(arg!(-o --"out" <FILE> "Save the QR code to the given file instead of showing it. Example: 'poster.png'").required(false))
+                .arg(arg!(-f --"format" <FORMAT> "Image format of the saved QR code, either png or svg. Defaults to the file extension").required(false))
+                .arg(arg!(-s --"size" <PIXELS> "Minimum width and height of the saved QR code, up to 1024").required(false))
+                .arg(arg!([NAME]))
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Show the audit log of changes to short URLs and API keys, newest first")
+                .arg(arg!(--"actor" <ID> "Only show changes made by the API key with the given ID").required(false))
+                .arg(arg!(--"action" <ACTION> "Only show the given action. One of link:create, link:update, link:delete, key:create, key:rotate or key:delete").required(false))
+                .arg(arg!(--"target" <TARGET> "Only show changes to the given short URL name or API key ID").required(false))
+                .arg(arg!(--"since" <TIMESTAMP> "Only show changes at or after the given RFC 3339 timestamp").required(false))
+                .arg(arg!(--"until" <TIMESTAMP> "Only show changes before the given RFC 3339 timestamp").required(false))
+                .arg(arg!(-l --"limit" <COUNT> "Maximum amount of entries to show").required(false))
+                .arg(arg!(-o --"offset" <COUNT> "Amount of entries to skip, for paging through results").required(false))
+        )
         .subcommand(
             Command::new("key")
                 .about("Manage API keys")
@@ -38,10 +97,17 @@ fn main() {
                 .subcommands( vec![
                     Command::new("create")
                         .about("Create a new API key")
-                        .arg(arg!(-d --description <DESCRIPTION> "Sets description of the created API key. Example: 'This key belongs to x user'").required(false)),
+                        .arg(arg!(-d --description <DESCRIPTION> "Sets description of the created API key. Example: 'This key belongs to x user'").required(false))
+                        .arg(arg!(-s --scope <SCOPE> "Grants a scope to the created API key. Can be repeated. One of links:read, links:write, links:delete, links:admin, stats:read or keys:admin. Defaults to all but the admin scopes").required(false).multiple_occurrences(true))
+                        .arg(arg!(-t --"ttl" <SECONDS> "Optionally expire the API key after the given amount of seconds").required(false))
+                        .arg(arg!(--"expires-at" <TIMESTAMP> "Optionally expire the API key at the given RFC 3339 timestamp. Example: '2022-06-01T00:00:00Z'").required(false)),
+                    Command::new("rotate")
+                        .about("Issue a new secret for an existing API key, keeping its scopes and links")
+                        .arg(arg!([ID] "ID of the API key, as shown by 'key list'"))
+                        .arg(arg!(-g --"grace" <SECONDS> "Keep the old secret working for the given amount of seconds, greater than zero").required(false)),
                     Command::new("delete")
                         .about("Delete existing API key")
-                        .arg(arg!([Key])),
+                        .arg(arg!([ID] "ID of the API key, as shown by 'key list'")),
                     Command::new("list")
                         .about("List API keys")
                 ])
@@ -55,10 +121,30 @@ fn main() {
             let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
             New::handle(context);
         },
+        Some(("edit", sub_matches)) => {
+            let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
+            Edit::handle(context);
+        },
         Some(("delete", sub_matches)) => {
             let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
             Delete::handle(context);
         },
+        Some(("list", sub_matches)) => {
+            let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
+            List::handle(context);
+        },
+        Some(("info", sub_matches)) => {
+            let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
+            Info::handle(context);
+        },
+        Some(("qr", sub_matches)) => {
+            let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
+            Qr::handle(context);
+        },
+        Some(("audit", sub_matches)) => {
+            let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
+            Audit::handle(context);
+        },
         Some(("key", sub_matches)) => {
             let context = CommandData::new(app.clone(), sub_matches.clone(), conf);
             Key::handle(context);
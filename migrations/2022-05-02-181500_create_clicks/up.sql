@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS clicks (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    url_id varchar(128) NOT NULL,
+    clicked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    referrer TEXT,
+    user_agent TEXT
+);
+
+CREATE INDEX idx_clicks_url_id
+    ON clicks (url_id);
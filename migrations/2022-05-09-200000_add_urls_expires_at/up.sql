@@ -0,0 +1,4 @@
+ALTER TABLE urls ADD COLUMN expires_at TIMESTAMP;
+
+CREATE INDEX idx_urls_expires_at
+    ON urls (expires_at);
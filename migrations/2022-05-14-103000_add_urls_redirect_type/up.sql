@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN redirect_type INTEGER;
\ No newline at end of file
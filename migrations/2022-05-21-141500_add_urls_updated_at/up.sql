@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN updated_at TIMESTAMP;
\ No newline at end of file
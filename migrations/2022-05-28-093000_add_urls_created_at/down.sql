@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_urls_created_at;
+ALTER TABLE urls DROP COLUMN created_at;
\ No newline at end of file
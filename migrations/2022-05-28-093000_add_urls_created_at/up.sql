@@ -0,0 +1,7 @@
+ALTER TABLE urls ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+
+-- Links created before this column existed have no known creation time, so use the time of the migration
+UPDATE urls SET created_at = CURRENT_TIMESTAMP;
+
+CREATE INDEX idx_urls_created_at
+    ON urls (created_at);
\ No newline at end of file
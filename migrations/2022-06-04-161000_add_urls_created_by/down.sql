@@ -0,0 +1 @@
+ALTER TABLE urls DROP COLUMN created_by;
\ No newline at end of file
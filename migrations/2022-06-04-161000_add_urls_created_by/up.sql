@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN created_by BIGINT;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN title TEXT;
\ No newline at end of file
@@ -0,0 +1,16 @@
+-- Hashed keys can't be turned back into plaintext, so only keys that haven't been hashed yet remain usable
+CREATE TABLE api_keys_plain (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    key TEXT NOT NULL UNIQUE,
+    description TEXT
+);
+
+INSERT INTO api_keys_plain (id, key, description)
+    SELECT id, COALESCE(legacy_key, key_hash), description FROM api_keys;
+
+DROP INDEX IF EXISTS idx_api_keys_key_prefix;
+DROP TABLE api_keys;
+ALTER TABLE api_keys_plain RENAME TO api_keys;
+
+CREATE UNIQUE INDEX idx_api_keys_key
+    ON api_keys (key);
\ No newline at end of file
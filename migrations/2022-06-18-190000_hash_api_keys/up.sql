@@ -0,0 +1,20 @@
+-- Keys are only stored as a salted hash from now on. Existing plaintext keys are moved to legacy_key and
+-- hashed by the application on startup, as SQLite has no built-in hash function to do it here.
+CREATE TABLE api_keys_hashed (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL DEFAULT '',
+    key_salt TEXT NOT NULL DEFAULT '',
+    legacy_key TEXT,
+    description TEXT
+);
+
+INSERT INTO api_keys_hashed (id, key_prefix, legacy_key, description)
+    SELECT id, substr(key, 1, 8), key, description FROM api_keys;
+
+DROP INDEX IF EXISTS idx_api_keys_key;
+DROP TABLE api_keys;
+ALTER TABLE api_keys_hashed RENAME TO api_keys;
+
+CREATE INDEX idx_api_keys_key_prefix
+    ON api_keys (key_prefix);
\ No newline at end of file
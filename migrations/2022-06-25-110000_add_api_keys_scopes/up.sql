@@ -0,0 +1,4 @@
+ALTER TABLE api_keys ADD COLUMN scopes TEXT NOT NULL DEFAULT '';
+
+-- Keys created before scopes existed had full access, so keep it that way
+UPDATE api_keys SET scopes = 'links:read,links:write,links:delete,stats:read,keys:admin';
\ No newline at end of file
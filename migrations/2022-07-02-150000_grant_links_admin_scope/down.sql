@@ -0,0 +1 @@
+UPDATE api_keys SET scopes = trim(replace(',' || scopes || ',', ',links:admin,', ','), ',');
\ No newline at end of file
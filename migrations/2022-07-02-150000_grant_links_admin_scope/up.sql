@@ -0,0 +1,2 @@
+-- Key administrators could already manage every link, so let them keep doing so now that links are owned by keys
+UPDATE api_keys SET scopes = scopes || ',links:admin' WHERE ',' || scopes || ',' LIKE '%,keys:admin,%';
\ No newline at end of file
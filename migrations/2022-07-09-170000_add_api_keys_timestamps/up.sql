@@ -0,0 +1,6 @@
+ALTER TABLE api_keys ADD COLUMN expires_at TIMESTAMP;
+ALTER TABLE api_keys ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+ALTER TABLE api_keys ADD COLUMN last_used_at TIMESTAMP;
+
+-- Keys created before this column existed have no known creation time, so use the time of the migration
+UPDATE api_keys SET created_at = CURRENT_TIMESTAMP;
\ No newline at end of file
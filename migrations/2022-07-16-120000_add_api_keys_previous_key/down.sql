@@ -0,0 +1,5 @@
+DROP INDEX idx_api_keys_previous_key_prefix;
+ALTER TABLE api_keys DROP COLUMN previous_key_expires_at;
+ALTER TABLE api_keys DROP COLUMN previous_key_salt;
+ALTER TABLE api_keys DROP COLUMN previous_key_hash;
+ALTER TABLE api_keys DROP COLUMN previous_key_prefix;
\ No newline at end of file
@@ -0,0 +1,7 @@
+-- Rotated out secret of a key, accepted until previous_key_expires_at to give clients time to switch over
+ALTER TABLE api_keys ADD COLUMN previous_key_prefix TEXT;
+ALTER TABLE api_keys ADD COLUMN previous_key_hash TEXT;
+ALTER TABLE api_keys ADD COLUMN previous_key_salt TEXT;
+ALTER TABLE api_keys ADD COLUMN previous_key_expires_at TIMESTAMP;
+
+CREATE INDEX idx_api_keys_previous_key_prefix ON api_keys (previous_key_prefix);
\ No newline at end of file
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor_key_id BIGINT,
+    action TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ip TEXT
+);
+
+CREATE INDEX idx_audit_log_created_at
+    ON audit_log (created_at);
+CREATE INDEX idx_audit_log_actor_key_id
+    ON audit_log (actor_key_id);
+CREATE INDEX idx_audit_log_target_id
+    ON audit_log (target_id);
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP INDEX idx_api_keys_subject;
+ALTER TABLE api_keys DROP COLUMN subject;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Callers authenticated with a JWT get a key record without a secret, so the links they create have an owner
+ALTER TABLE api_keys ADD COLUMN subject TEXT;
+
+CREATE UNIQUE INDEX idx_api_keys_subject ON api_keys (subject);
\ No newline at end of file
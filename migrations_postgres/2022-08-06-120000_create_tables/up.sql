@@ -0,0 +1,70 @@
+-- PostgreSQL starts out with the schema the SQLite migrations have built up to this point
+CREATE TABLE IF NOT EXISTS urls (
+    id VARCHAR(128) NOT NULL PRIMARY KEY,
+    url TEXT NOT NULL,
+    expires_at TIMESTAMP,
+    redirect_type INTEGER,
+    updated_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_by BIGINT,
+    title TEXT
+);
+
+CREATE INDEX idx_urls_expires_at
+    ON urls (expires_at);
+CREATE INDEX idx_urls_created_at
+    ON urls (created_at);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL DEFAULT '',
+    key_salt TEXT NOT NULL DEFAULT '',
+    legacy_key TEXT,
+    description TEXT,
+    scopes TEXT NOT NULL DEFAULT '',
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    previous_key_prefix TEXT,
+    previous_key_hash TEXT,
+    previous_key_salt TEXT,
+    previous_key_expires_at TIMESTAMP,
+    subject TEXT
+);
+
+CREATE INDEX idx_api_keys_key_prefix
+    ON api_keys (key_prefix);
+CREATE INDEX idx_api_keys_previous_key_prefix
+    ON api_keys (previous_key_prefix);
+CREATE UNIQUE INDEX idx_api_keys_subject
+    ON api_keys (subject);
+
+CREATE TABLE IF NOT EXISTS clicks (
+    id SERIAL NOT NULL PRIMARY KEY,
+    url_id VARCHAR(128) NOT NULL,
+    clicked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    referrer TEXT,
+    user_agent TEXT
+);
+
+CREATE INDEX idx_clicks_url_id
+    ON clicks (url_id);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id SERIAL NOT NULL PRIMARY KEY,
+    actor_key_id BIGINT,
+    action TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ip TEXT
+);
+
+CREATE INDEX idx_audit_log_created_at
+    ON audit_log (created_at);
+CREATE INDEX idx_audit_log_actor_key_id
+    ON audit_log (actor_key_id);
+CREATE INDEX idx_audit_log_target_id
+    ON audit_log (target_id);
\ No newline at end of file